@@ -0,0 +1,210 @@
+//! Per-component audit trail at `Official/<comp>/audit.jsonl`: prompts, raw answers,
+//! parsed verdicts and every rejected url with its reason.
+
+use std::{
+    collections::HashSet,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use color_eyre::eyre::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::llm_api::{entities::LLMMsg, usage::Stage};
+
+const AUDIT_FNAME: &str = "audit.jsonl";
+
+/// where an llm answer came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnswerSource {
+    Api,
+    Cache,
+    Cassette,
+}
+
+/// why a url was dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectReason {
+    /// `.sig` or `.exe` link
+    Filtered,
+    /// excluded by the include/exclude globs of a component override
+    Excluded,
+    Inaccessible,
+    /// download page on a host that is not an official site
+    NotOfficial,
+    NotSource,
+    NotRelated,
+    /// no version found in the file name
+    NoVersion,
+    /// left out of the batched classification answer
+    Unclassified,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// a request to the llm along with its raw answer
+    Prompt {
+        stage: Stage,
+        model: String,
+        messages: Vec<LLMMsg>,
+        answer: String,
+        source: AnswerSource,
+    },
+    /// decision parsed from an answer
+    Verdict {
+        stage: Stage,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        url: Option<String>,
+        verdict: Value,
+    },
+    Rejected {
+        url: String,
+        reason: RejectReason,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        detail: Option<String>,
+    },
+}
+
+impl AuditEvent {
+    pub fn rejected(url: &str, reason: RejectReason) -> Self {
+        Self::Rejected {
+            url: url.to_string(),
+            reason,
+            detail: None,
+        }
+    }
+
+    pub fn rejected_for(url: &str, reason: RejectReason, detail: &str) -> Self {
+        Self::Rejected {
+            url: url.to_string(),
+            reason,
+            detail: Some(detail.to_string()),
+        }
+    }
+
+    pub fn verdict<T: Serialize>(stage: Stage, url: Option<&str>, verdict: &T) -> Self {
+        Self::Verdict {
+            stage,
+            url: url.map(str::to_string),
+            verdict: serde_json::to_value(verdict).unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    ts: String,
+    /// start time of the run, tells runs apart in the appended file
+    run: &'a str,
+    component: &'a str,
+    #[serde(flatten)]
+    event: &'a AuditEvent,
+}
+
+/// Appends audit events to the file of each component, disabled by `download.audit = false`.
+pub struct AuditLog {
+    enabled: bool,
+    /// `Official` dir of the download dir, holding a dir per component
+    dir: PathBuf,
+    run: String,
+    /// components whose dir is known to exist, so it is created once per run
+    comp_dirs: Mutex<HashSet<String>>,
+}
+
+impl AuditLog {
+    pub fn new(enabled: bool, dir: PathBuf) -> Self {
+        Self {
+            enabled,
+            dir,
+            run: chrono::Local::now().to_rfc3339(),
+            comp_dirs: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn audit_fpath(&self, comp_name: &str) -> PathBuf {
+        self.dir.join(comp_name).join(AUDIT_FNAME)
+    }
+
+    /// best effort, a failed write is only logged
+    pub fn record(&self, comp_name: &str, event: &AuditEvent) {
+        if !self.enabled {
+            return;
+        }
+        if let Err(e) = self.append(comp_name, event) {
+            log::debug!("audit event of {} not written: {}", comp_name, e);
+        }
+    }
+
+    fn append(&self, comp_name: &str, event: &AuditEvent) -> Result<()> {
+        let rec = AuditRecord {
+            ts: chrono::Local::now().to_rfc3339(),
+            run: &self.run,
+            component: comp_name,
+            event,
+        };
+        let line = serde_json::to_string(&rec)?;
+        let mut comp_dirs = self.comp_dirs.lock().expect("audit log poisoned");
+        if !comp_dirs.contains(comp_name) {
+            // the `Official` dir itself has to exist already
+            let comp_dir = self.dir.join(comp_name);
+            if !comp_dir.is_dir() {
+                fs::create_dir(&comp_dir)?;
+            }
+            comp_dirs.insert(comp_name.to_string());
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.audit_fpath(comp_name))?;
+        writeln!(file, "{line}")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_audit_lines() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path().to_path_buf();
+        let audit = AuditLog::new(true, dir.clone());
+        audit.record(
+            "foo",
+            &AuditEvent::rejected("https://a.org/x.sig", RejectReason::Filtered),
+        );
+        audit.record(
+            "foo",
+            &AuditEvent::verdict(Stage::SourceCheck, Some("https://a.org/x.tar.gz"), &true),
+        );
+
+        let text = fs::read_to_string(audit.audit_fpath("foo"))?;
+        let lines: Vec<Value> = text
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["component"], "foo");
+        assert_eq!(lines[0]["run"], lines[1]["run"]);
+        assert!(lines[0]["ts"].is_string());
+        assert_eq!(lines[0]["event"], "rejected");
+        assert_eq!(lines[0]["reason"], "filtered");
+        // an empty detail is left out
+        assert!(lines[0].get("detail").is_none());
+        assert_eq!(lines[1]["event"], "verdict");
+        assert_eq!(lines[1]["verdict"], json!(true));
+
+        AuditLog::new(false, dir.clone())
+            .record("bar", &AuditEvent::rejected("x", RejectReason::Filtered));
+        assert!(!dir.join("bar").exists());
+        Ok(())
+    }
+}
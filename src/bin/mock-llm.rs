@@ -0,0 +1,33 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use color_eyre::eyre::Result;
+use official_download::{
+    mock_llm::{MockScript, MockServer},
+    utils::init_report_utils,
+};
+
+/// scripted OpenAI compatible llm server for offline runs
+#[derive(Debug, Parser)]
+#[command(name = "mock-llm", version)]
+struct MockCli {
+    /// toml file with `[[rules]]` and `[[pages]]`
+    script: PathBuf,
+    /// address to listen on
+    #[arg(short, long, default_value = "127.0.0.1:8089")]
+    addr: String,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    init_report_utils()?;
+    let cli = MockCli::parse();
+    let script = MockScript::load(&cli.script)?;
+    let server = MockServer::bind(&cli.addr, script).await?;
+    log::info!(
+        "mock llm listening, set `api.api_url = \"{}\"`",
+        server.chat_url()
+    );
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
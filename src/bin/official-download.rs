@@ -0,0 +1,14 @@
+use std::process::ExitCode;
+
+use clap::Parser;
+use color_eyre::eyre::Result;
+use official_download::{
+    commands::run_cli, config::cli_config::CliConfig, utils::init_report_utils,
+};
+
+#[tokio::main]
+async fn main() -> Result<ExitCode> {
+    init_report_utils()?;
+    let cli = CliConfig::parse();
+    run_cli(&cli).await
+}
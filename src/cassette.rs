@@ -0,0 +1,221 @@
+//! Record and replay of all outgoing traffic: llm calls, url checks, page fetches and downloads.
+//!
+//! A cassette is a json lines file, one exchange per line. Downloaded files are kept as
+//! their size and sha256 only, replaying a download writes no file.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use color_eyre::eyre::Result;
+use eyre::{WrapErr, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    llm_api::{provider::Completion, search::Citation, usage::TokenUsage},
+    utils::UrlAccess,
+};
+
+/// one request along with what came back
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Exchange {
+    /// llm completion, keyed like the llm cache
+    Llm {
+        key: String,
+        model: String,
+        content: String,
+        #[serde(default)]
+        usage: Option<TokenUsage>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        citations: Vec<Citation>,
+    },
+    /// accessibility check of a url, HEAD possibly followed by a ranged GET
+    Head {
+        url: String,
+        #[serde(flatten)]
+        access: UrlAccess,
+    },
+    /// page fetched for its links
+    Get {
+        url: String,
+        status: u16,
+        body: String,
+    },
+    /// downloaded file, the body is not kept
+    Download {
+        url: String,
+        status: u16,
+        size: u64,
+        sha256: String,
+    },
+}
+
+impl Exchange {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Llm { .. } => "llm",
+            Self::Head { .. } => "head",
+            Self::Get { .. } => "get",
+            Self::Download { .. } => "download",
+        }
+    }
+
+    /// what a replayed request is matched by
+    fn key(&self) -> (&'static str, String) {
+        let key = match self {
+            Self::Llm { key, .. } => key,
+            Self::Head { url, .. } | Self::Get { url, .. } | Self::Download { url, .. } => url,
+        };
+        (self.kind(), key.clone())
+    }
+
+    pub fn llm(key: &str, model: &str, ans: &Completion) -> Self {
+        Self::Llm {
+            key: key.to_string(),
+            model: model.to_string(),
+            content: ans.content.clone(),
+            usage: ans.usage,
+            citations: ans.citations.clone(),
+        }
+    }
+}
+
+/// how the cassette is used in this run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    Off,
+    /// append every exchange to the cassette (`--record`)
+    Record,
+    /// answer every request from the cassette, no network (`--replay`)
+    Replay,
+}
+
+pub struct Cassette {
+    mode: CassetteMode,
+    writer: Mutex<Option<fs::File>>,
+    /// recorded exchanges by kind and key, in recording order
+    tape: Mutex<HashMap<(&'static str, String), VecDeque<Exchange>>>,
+}
+
+impl Cassette {
+    pub fn off() -> Self {
+        Self {
+            mode: CassetteMode::Off,
+            writer: Mutex::new(None),
+            tape: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// start a new cassette at `fpath`, replacing an existing one
+    pub fn record(fpath: &Path) -> Result<Self> {
+        let file = fs::File::create(fpath)
+            .wrap_err_with(|| format!("Failed to create cassette {fpath:?}"))?;
+        Ok(Self {
+            mode: CassetteMode::Record,
+            writer: Mutex::new(Some(file)),
+            ..Self::off()
+        })
+    }
+
+    pub fn replay(fpath: &Path) -> Result<Self> {
+        let file =
+            fs::File::open(fpath).wrap_err_with(|| format!("Failed to open cassette {fpath:?}"))?;
+        let mut tape: HashMap<_, VecDeque<Exchange>> = HashMap::new();
+        for (idx, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let ex: Exchange = serde_json::from_str(&line)
+                .wrap_err_with(|| format!("Invalid exchange at line {} of {fpath:?}", idx + 1))?;
+            tape.entry(ex.key()).or_default().push_back(ex);
+        }
+        Ok(Self {
+            mode: CassetteMode::Replay,
+            tape: Mutex::new(tape),
+            ..Self::off()
+        })
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    pub fn is_replay(&self) -> bool {
+        self.mode == CassetteMode::Replay
+    }
+
+    /// store an exchange when recording
+    pub fn put(&self, ex: &Exchange) {
+        if self.mode != CassetteMode::Record {
+            return;
+        }
+        let mut guard = self.writer.lock().expect("cassette poisoned");
+        let Some(file) = guard.as_mut() else {
+            return;
+        };
+        let res = serde_json::to_string(ex)
+            .map_err(eyre::Report::from)
+            .and_then(|line| Ok(writeln!(file, "{line}")?));
+        if let Err(e) = res {
+            log::warn!("Failed to record {} exchange: {}", ex.kind(), e);
+        }
+    }
+
+    /// Next recorded exchange of `kind` for `key`, the last one is served again once the others are used up.
+    pub fn take(&self, kind: &'static str, key: &str) -> Result<Exchange> {
+        let mut tape = self.tape.lock().expect("cassette poisoned");
+        let Some(queue) = tape.get_mut(&(kind, key.to_string())) else {
+            bail!("No {} exchange for {} in the cassette", kind, key);
+        };
+        match queue.len() {
+            0 => bail!("No {} exchange for {} in the cassette", kind, key),
+            1 => Ok(queue[0].clone()),
+            _ => Ok(queue.pop_front().expect("queue is not empty")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_replay() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let fpath = tmp.path().join("run.jsonl");
+        let head = |status: u16| Exchange::Head {
+            url: "https://a.org/x/".to_string(),
+            access: UrlAccess {
+                status: Some(status),
+                ..Default::default()
+            },
+        };
+        let cas = Cassette::record(&fpath)?;
+        cas.put(&head(404));
+        cas.put(&head(200));
+        cas.put(&Exchange::Download {
+            url: "https://a.org/x/x-1.0.tar.gz".to_string(),
+            status: 200,
+            size: 3,
+            sha256: "ab".to_string(),
+        });
+        drop(cas);
+
+        let cas = Cassette::replay(&fpath)?;
+        let accessible =
+            |ex: Exchange| matches!(ex, Exchange::Head { access, .. } if access.is_accessible());
+        assert!(!accessible(cas.take("head", "https://a.org/x/")?));
+        // the last answer repeats
+        assert!(accessible(cas.take("head", "https://a.org/x/")?));
+        assert!(accessible(cas.take("head", "https://a.org/x/")?));
+        assert!(cas.take("get", "https://a.org/x/").is_err());
+        assert!(cas.take("download", "https://a.org/x/x-1.0.tar.gz").is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use std::process::ExitCode;
+
+use color_eyre::eyre::Result;
+use output::{
+    AskReport, CheckReport, DiscoverItem, DiscoverReport, FetchReport, LinksReport, emit,
+};
+
+use crate::{
+    config::{
+        build_config,
+        cli_config::{CliConfig, Command, ConfigAction, LlmAction},
+        validate::validate,
+    },
+    context::Context,
+    download::{self, download_link::DLEntryPool, download_page::get_download_page_batch},
+    llm_api::{
+        get_llm_completion,
+        usage::{CallMeta, Stage},
+    },
+};
+
+pub mod output;
+
+/// report every config problem, fails if any is found
+fn check_config(cli: &CliConfig) -> Result<ExitCode> {
+    let config = build_config(cli)?;
+    let issues = validate(&config);
+    let report = CheckReport {
+        ok: issues.is_empty(),
+        issues,
+    };
+    emit(&report, cli.json)?;
+    if report.ok {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+async fn discover(ctx: &Context, components: &[String], json: bool) -> Result<()> {
+    let comp_name_list: Vec<&str> = components.iter().map(String::as_str).collect();
+    let page_list = get_download_page_batch(ctx, &comp_name_list).await?;
+    let pages = page_list
+        .into_iter()
+        .map(|(component, page)| DiscoverItem { component, page })
+        .collect();
+    emit(&DiscoverReport { pages }, json)
+}
+
+async fn links(
+    ctx: &Context,
+    page_url: &str,
+    component: &str,
+    save: Option<&std::path::Path>,
+    json: bool,
+) -> Result<()> {
+    let (pool, abnormal) = DLEntryPool::from_page_url(ctx, page_url, component).await?;
+    if let Some(fpath) = save {
+        pool.save_to(fpath)?;
+    }
+    let report = LinksReport {
+        component: component.to_string(),
+        page_url: page_url.to_string(),
+        abnormal,
+        entries: pool.entries,
+        saved_to: save.map(|p| p.to_path_buf()),
+    };
+    emit(&report, json)
+}
+
+async fn fetch(ctx: &Context, pool_fpath: &std::path::Path, json: bool) -> Result<()> {
+    let pool = DLEntryPool::load_from(pool_fpath)?;
+    let outcomes = pool.download(ctx).await?;
+    let report = FetchReport {
+        component: pool.comp_name,
+        outcomes,
+    };
+    emit(&report, json)
+}
+
+/// dispatch a parsed command line
+pub async fn run_cli(cli: &CliConfig) -> Result<ExitCode> {
+    if let Command::Config {
+        action: ConfigAction::Check,
+    } = &cli.command
+    {
+        return check_config(cli);
+    }
+
+    let ctx = Context::from_cli(cli)?;
+    let json = cli.json;
+    match &cli.command {
+        Command::Discover { components } => discover(&ctx, components, json).await?,
+        Command::Links {
+            page_url,
+            component,
+            save,
+        } => links(&ctx, page_url, component, save.as_deref(), json).await?,
+        Command::Fetch { pool } => fetch(&ctx, pool, json).await?,
+        Command::Run => {
+            let summary = download::download(&ctx).await?;
+            emit(&summary, json)?;
+        }
+        Command::Llm {
+            action: LlmAction::Ask { prompt },
+        } => {
+            let meta = CallMeta::new("-", Stage::Adhoc);
+            let answer = get_llm_completion(&ctx, &meta, prompt).await?;
+            let report = AskReport {
+                prompt: prompt.clone(),
+                answer,
+            };
+            emit(&report, json)?;
+        }
+        Command::Config { .. } => unreachable!("config commands need no context"),
+    }
+    Ok(ExitCode::SUCCESS)
+}
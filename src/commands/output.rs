@@ -0,0 +1,182 @@
+use color_eyre::eyre::Result;
+use serde::Serialize;
+use std::{fmt::Write, path::PathBuf};
+
+use crate::{
+    config::validate::ConfigIssue,
+    download::{
+        RunSummary, download_link::entities::DLEntry, download_page::entities::PageAns,
+        file_download::DLOutcome,
+    },
+};
+
+/// command result, printed either human readable or as json
+pub trait Report: Serialize {
+    fn human(&self) -> String;
+}
+
+pub fn emit<T: Report>(report: &T, json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(report)?);
+    } else {
+        println!("{}", report.human());
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct DiscoverItem {
+    pub component: String,
+    pub page: Option<PageAns>,
+}
+
+#[derive(Serialize)]
+pub struct DiscoverReport {
+    pub pages: Vec<DiscoverItem>,
+}
+
+impl Report for DiscoverReport {
+    fn human(&self) -> String {
+        let mut out = String::new();
+        for it in self.pages.iter() {
+            let url = it
+                .page
+                .as_ref()
+                .and_then(|page| page.site_url())
+                .unwrap_or("<not found>");
+            let _ = write!(out, "{}: {}", it.component, url);
+            if let Some(agreement) = it.page.as_ref().and_then(|page| page.agreement) {
+                let _ = write!(out, " (agreement {:.2})", agreement);
+            }
+            let _ = writeln!(out);
+        }
+        let found = self
+            .pages
+            .iter()
+            .filter(|it| it.page.as_ref().is_some_and(PageAns::is_available))
+            .count();
+        let _ = write!(out, "{}/{} download pages found", found, self.pages.len());
+        out
+    }
+}
+
+#[derive(Serialize)]
+pub struct LinksReport {
+    pub component: String,
+    pub page_url: String,
+    pub abnormal: bool,
+    pub entries: Vec<DLEntry>,
+    pub saved_to: Option<PathBuf>,
+}
+
+impl Report for LinksReport {
+    fn human(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{} download entries for {} from {}",
+            self.entries.len(),
+            self.component,
+            self.page_url
+        );
+        for ent in self.entries.iter() {
+            let _ = writeln!(out, "  {}: {}", ent.fname_ext, ent.url);
+        }
+        if self.abnormal {
+            let _ = writeln!(out, "page is abnormal: no usable links found");
+        }
+        if let Some(fpath) = self.saved_to.as_ref() {
+            let _ = writeln!(out, "pool saved to {:?}", fpath);
+        }
+        out.trim_end().to_string()
+    }
+}
+
+#[derive(Serialize)]
+pub struct FetchReport {
+    pub component: String,
+    pub outcomes: Vec<DLOutcome>,
+}
+
+impl Report for FetchReport {
+    fn human(&self) -> String {
+        let mut out = String::new();
+        for it in self.outcomes.iter() {
+            match it.error.as_ref() {
+                None => {
+                    let _ = writeln!(out, "  ok     {}", it.fname_ext);
+                }
+                Some(e) => {
+                    let _ = writeln!(out, "  failed {}: {}", it.fname_ext, e);
+                }
+            }
+        }
+        let ok_cnt = self.outcomes.iter().filter(|it| it.error.is_none()).count();
+        let _ = write!(
+            out,
+            "{}/{} entries of {} downloaded",
+            ok_cnt,
+            self.outcomes.len(),
+            self.component
+        );
+        out
+    }
+}
+
+impl Report for RunSummary {
+    fn human(&self) -> String {
+        format!(
+            "{} components processed, {} download pages found, {} abnormal\n\
+             available pages: {:?}\n\
+             abnormal pages: {:?}\n\
+             llm calls: {} ({} cached), tokens: {} prompt + {} completion, estimated cost: {}\n\
+             usage details: {:?}",
+            self.component_count,
+            self.available_count,
+            self.abnormal_count,
+            self.available_fpath,
+            self.abnormal_fpath,
+            self.usage.calls,
+            self.usage.cached_calls,
+            self.usage.tokens.prompt_tokens,
+            self.usage.tokens.completion_tokens,
+            self.usage
+                .cost
+                .map(|cost| format!("${cost:.4}"))
+                .unwrap_or_else(|| "unknown (no price for some model)".to_string()),
+            self.usage_fpath
+        )
+    }
+}
+
+#[derive(Serialize)]
+pub struct AskReport {
+    pub prompt: String,
+    pub answer: String,
+}
+
+impl Report for AskReport {
+    fn human(&self) -> String {
+        self.answer.clone()
+    }
+}
+
+#[derive(Serialize)]
+pub struct CheckReport {
+    pub ok: bool,
+    pub issues: Vec<ConfigIssue>,
+}
+
+impl Report for CheckReport {
+    fn human(&self) -> String {
+        if self.ok {
+            return "config ok".to_string();
+        }
+        let mut out = String::new();
+        for it in self.issues.iter() {
+            let _ = writeln!(out, "{it}");
+        }
+        let _ = write!(out, "{} problem(s) found in config", self.issues.len());
+        out
+    }
+}
@@ -0,0 +1,146 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use color_eyre::eyre::Result;
+use eyre::WrapErr;
+use glob::Pattern;
+use regex::Regex;
+use serde::Deserialize;
+
+/// one entry of `components.toml` as written by the user
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CompOverrideSpec {
+    site_url: Option<String>,
+    #[serde(default)]
+    skip: bool,
+    version_regex: Option<String>,
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+    max_version_count: Option<usize>,
+    #[serde(default)]
+    allow_hosts: Vec<String>,
+    #[serde(default)]
+    deny_hosts: Vec<String>,
+}
+
+/// manual curation for a single component
+#[derive(Debug, Clone, Default)]
+pub struct CompOverride {
+    /// pinned download page, the llm is not asked
+    pub site_url: Option<String>,
+    /// leave the component out entirely
+    pub skip: bool,
+    /// version extracted from file names, the capture group named `ver` is used if present
+    pub version_re: Option<Regex>,
+    /// url globs, a link must match one of them if any is given
+    pub include: Vec<Pattern>,
+    /// url globs, a link matching any of them is dropped
+    pub exclude: Vec<Pattern>,
+    pub max_version_count: Option<usize>,
+    /// hosts official for this component, ahead of the `[policy]` section
+    pub allow_hosts: Vec<String>,
+    /// hosts never official for this component
+    pub deny_hosts: Vec<String>,
+}
+
+impl CompOverride {
+    fn from_spec(spec: CompOverrideSpec) -> Result<Self> {
+        let version_re = spec.version_regex.as_deref().map(Regex::new).transpose()?;
+        let compile = |globs: &[String]| -> Result<Vec<Pattern>> {
+            let mut pats = vec![];
+            for it in globs.iter() {
+                pats.push(Pattern::new(it).wrap_err_with(|| format!("invalid glob {it:?}"))?);
+            }
+            Ok(pats)
+        };
+        Ok(Self {
+            site_url: spec.site_url,
+            skip: spec.skip,
+            version_re,
+            include: compile(&spec.include)?,
+            exclude: compile(&spec.exclude)?,
+            max_version_count: spec.max_version_count,
+            allow_hosts: spec.allow_hosts,
+            deny_hosts: spec.deny_hosts,
+        })
+    }
+
+    /// whether an extracted link passes the include/exclude globs
+    pub fn is_url_allowed(&self, url: &str) -> bool {
+        if self.exclude.iter().any(|pat| pat.matches(url)) {
+            return false;
+        }
+        self.include.is_empty() || self.include.iter().any(|pat| pat.matches(url))
+    }
+}
+
+/// per-component overrides from `components.toml`, keyed by component name
+#[derive(Debug, Clone, Default)]
+pub struct CompOverrides {
+    map: HashMap<String, CompOverride>,
+}
+
+impl CompOverrides {
+    pub fn from_toml(toml_str: &str) -> Result<Self> {
+        let specs: HashMap<String, CompOverrideSpec> = toml::from_str(toml_str)?;
+        let mut map = HashMap::new();
+        for (comp_name, spec) in specs {
+            let ovr = CompOverride::from_spec(spec)
+                .wrap_err_with(|| format!("invalid override for component {comp_name}"))?;
+            map.insert(comp_name, ovr);
+        }
+        Ok(Self { map })
+    }
+
+    /// load overrides, a missing file means no overrides
+    pub fn load(fpath: &Path) -> Result<Self> {
+        if !fpath.is_file() {
+            return Ok(Self::default());
+        }
+        let toml_str = fs::read_to_string(fpath)?;
+        let ovrs = Self::from_toml(&toml_str).wrap_err_with(|| format!("in {:?}", fpath))?;
+        log::info!("{} component overrides loaded from {:?}", ovrs.len(), fpath);
+        Ok(ovrs)
+    }
+
+    pub fn get(&self, comp_name: &str) -> Option<&CompOverride> {
+        self.map.get(comp_name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_override_globs() -> Result<()> {
+        let ovrs = CompOverrides::from_toml(
+            r#"
+[wireless]
+site_url = "https://hewlettpackard.github.io/wireless-tools/"
+
+[coreutils]
+include = ["*.tar.xz"]
+exclude = ["*-rc*"]
+max_version_count = 2
+"#,
+        )?;
+        assert!(ovrs.get("wireless").unwrap().site_url.is_some());
+        let ovr = ovrs.get("coreutils").unwrap();
+        assert!(ovr.is_url_allowed("https://ftp.gnu.org/gnu/coreutils/coreutils-9.5.tar.xz"));
+        assert!(!ovr.is_url_allowed("https://ftp.gnu.org/gnu/coreutils/coreutils-9.5.tar.gz"));
+        assert!(!ovr.is_url_allowed("https://ftp.gnu.org/gnu/coreutils/coreutils-9.6-rc1.tar.xz"));
+        assert!(CompOverrides::from_toml("[foo]\nsite = 1").is_err());
+        Ok(())
+    }
+}
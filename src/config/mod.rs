@@ -0,0 +1,347 @@
+use std::env;
+use std::path::PathBuf;
+
+use color_eyre::eyre::Result;
+use config::builder::{ConfigBuilder, DefaultState};
+use config::{Config, Environment, File, FileFormat, FileSourceFile};
+use eyre::bail;
+use file_config::AppConfig;
+use validate::validate;
+
+pub mod components;
+pub mod validate;
+
+/// project config file, relative to the working directory
+const CONFIG_FILENAME: &str = "config/config.toml";
+/// system wide config file
+const SYSTEM_CONFIG_FILENAME: &str = "/etc/official_download/config.toml";
+/// user config file, relative to the user config dir
+const USER_CONFIG_FILENAME: &str = "official_download/config.toml";
+/// prefix of environment variables, e.g. `OD_API__KEY` for `api.key`
+const ENV_PREFIX: &str = "OD";
+const ENV_SEPARATOR: &str = "__";
+
+pub mod file_config {
+    use crate::download::download_page::registry::RegistryEntry;
+    use crate::llm_api::config::{
+        AgentConfig, ApiConfig, LlmCacheConfig, PromptsConfig, VoteConfig,
+    };
+    use serde::Deserialize;
+
+    #[derive(Clone, Deserialize)]
+    /// app config primarily from config file
+    pub struct AppConfig {
+        pub api: ApiConfig,
+        pub download: DLConfig,
+        pub cache: LlmCacheConfig,
+        pub prompts: PromptsConfig,
+        pub vote: VoteConfig,
+        pub agent: AgentConfig,
+        pub heuristic: HeuristicConfig,
+        pub policy: PolicyConfig,
+        pub registry: RegistryConfig,
+        pub verify: VerifyConfig,
+    }
+
+    #[derive(Clone, Deserialize)]
+    pub struct DLConfig {
+        pub username: String,
+        pub max_version_count: usize,
+        pub base_dir: String,
+        /// per-component overrides, see [`crate::config::components`]
+        pub components_file: String,
+        /// write `Official/<comp>/audit.jsonl`
+        pub audit: bool,
+    }
+
+    /// `[heuristic]` section, the pipeline without any llm call (`--no-llm`)
+    #[derive(Clone, Deserialize)]
+    pub struct HeuristicConfig {
+        pub enabled: bool,
+        /// toml file of `component = "download page url"` pairs, tried before the registry
+        pub page_list: String,
+    }
+
+    /// `[policy]` section, which download pages count as official,
+    /// see [`crate::download::download_page::policy`]
+    #[derive(Clone, Deserialize)]
+    pub struct PolicyConfig {
+        /// hosts always official, a host also matches its subdomains
+        pub allow_hosts: Vec<String>,
+        /// hosts never official, checked before `allow_hosts`
+        pub deny_hosts: Vec<String>,
+        /// forges whose pages are not official, by name like `GitHub`
+        pub deny_forges: Vec<String>,
+        /// accept pages on mirror networks
+        pub allow_mirrors: bool,
+        /// mirror networks besides the built-in ones
+        pub mirror_hosts: Vec<String>,
+    }
+
+    /// `[verify]` section, content checks of download pages,
+    /// see [`crate::download::download_page::verify`]
+    #[derive(Clone, Deserialize)]
+    pub struct VerifyConfig {
+        pub enabled: bool,
+    }
+
+    /// `[registry]` section, well-known source hosts probed before the llm is asked,
+    /// see [`crate::download::download_page::registry`]
+    #[derive(Clone, Deserialize)]
+    pub struct RegistryConfig {
+        pub enabled: bool,
+        /// probe the built-in entries after `hosts`
+        pub builtin: bool,
+        /// entries of this config, tried first
+        #[serde(default)]
+        pub hosts: Vec<RegistryEntry>,
+    }
+}
+
+pub mod cli_config {
+    use std::path::PathBuf;
+
+    use clap::{Parser, Subcommand};
+    #[derive(Debug, Parser)]
+    #[command(name = "official-download", version)]
+    pub struct CliConfig {
+        /// base dir for download
+        #[arg(short, long, global = true)]
+        pub base_dir: Option<String>,
+
+        /// config file used in place of `config/config.toml`
+        #[arg(short, long, global = true)]
+        pub config: Option<String>,
+
+        /// neither read nor write the llm answer cache
+        #[arg(long, global = true)]
+        pub no_cache: bool,
+
+        /// ask the llm again and overwrite cached answers
+        #[arg(long, global = true, conflicts_with = "no_cache")]
+        pub refresh: bool,
+
+        /// write every llm call, url check, page fetch and download of the run to a cassette file
+        #[arg(long, global = true)]
+        pub record: Option<PathBuf>,
+
+        /// answer every request from a cassette written by `--record`, without network
+        #[arg(long, global = true, conflicts_with = "record")]
+        pub replay: Option<PathBuf>,
+
+        /// find pages and links by heuristics only, never ask the llm
+        #[arg(long, global = true)]
+        pub no_llm: bool,
+
+        /// print structured json instead of human readable output
+        #[arg(long, global = true)]
+        pub json: bool,
+
+        #[command(subcommand)]
+        pub command: Command,
+    }
+
+    #[derive(Debug, Subcommand)]
+    pub enum Command {
+        /// look up official download pages only
+        Discover {
+            /// component names
+            #[arg(required = true)]
+            components: Vec<String>,
+        },
+        /// collect download entries from a download page
+        Links {
+            page_url: String,
+            component: String,
+            /// save the entry pool for a later `fetch`
+            #[arg(short, long)]
+            save: Option<PathBuf>,
+        },
+        /// download entries from a pool saved by `links --save`
+        Fetch { pool: PathBuf },
+        /// run the whole pipeline over the GitHub component dirs
+        Run,
+        /// talk to the llm api directly
+        Llm {
+            #[command(subcommand)]
+            action: LlmAction,
+        },
+        /// inspect the loaded config
+        Config {
+            #[command(subcommand)]
+            action: ConfigAction,
+        },
+    }
+
+    #[derive(Debug, Subcommand)]
+    pub enum LlmAction {
+        /// send a single prompt and print the answer
+        Ask { prompt: String },
+    }
+
+    #[derive(Debug, Subcommand)]
+    pub enum ConfigAction {
+        /// validate the config and report every problem found
+        Check,
+    }
+}
+
+/// user config file under `$XDG_CONFIG_HOME`, falling back to `~/.config`
+fn get_user_config_fpath() -> Option<PathBuf> {
+    let cfg_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(cfg_dir.join(USER_CONFIG_FILENAME))
+}
+
+fn file_source(fpath: &str, required: bool) -> File<FileSourceFile, FileFormat> {
+    File::new(fpath, FileFormat::Toml).required(required)
+}
+
+/// built-in defaults, overridden by every other layer
+pub(crate) fn with_defaults(
+    builder: ConfigBuilder<DefaultState>,
+) -> Result<ConfigBuilder<DefaultState>> {
+    let builder = builder
+        .set_default("api.provider", "openai")?
+        .set_default("api.key", "")?
+        .set_default("api.model_id", "gpt-4o-mini")?
+        .set_default("api.temperature", 0.1)?
+        .set_default("api.max_tokens", 1024)?
+        .set_default("api.json_schema", true)?
+        .set_default("api.parallel", 10)?
+        .set_default("api.retry", 5)?
+        .set_default("api.check_retry", 1)?
+        .set_default("api.retry_delay", 1)?
+        .set_default("api.max_retry_delay", 60)?
+        .set_default("api.few_shot", 3)?
+        .set_default("api.link_batch_size", 40)?
+        .set_default("api.rpm", 0)?
+        .set_default("api.tpm", 0)?
+        .set_default("api.time_out", 5)?
+        .set_default("api.llm_time_out", 120)?
+        .set_default("download.max_version_count", 10)?
+        .set_default("download.components_file", "config/components.toml")?
+        .set_default("download.audit", true)?
+        .set_default("cache.enabled", true)?
+        .set_default("cache.ttl", 0)?
+        .set_default("cache.dir", "")?
+        .set_default("prompts.dir", "prompts")?
+        .set_default("vote.samples", 1)?
+        .set_default("vote.temperature", 0.7)?
+        .set_default("vote.min_agreement", 0.6)?
+        .set_default("agent.enabled", false)?
+        .set_default("agent.max_tool_calls", 6)?
+        .set_default("agent.max_page_chars", 6000)?
+        .set_default("agent.allow_private_hosts", false)?
+        .set_default("policy.allow_hosts", Vec::<String>::new())?
+        .set_default("policy.deny_hosts", Vec::<String>::new())?
+        .set_default("policy.deny_forges", vec!["GitHub", "GitLab"])?
+        .set_default("policy.allow_mirrors", false)?
+        .set_default("policy.mirror_hosts", Vec::<String>::new())?
+        .set_default("verify.enabled", true)?
+        .set_default("registry.enabled", true)?
+        .set_default("registry.builtin", true)?
+        .set_default("heuristic.enabled", false)?
+        .set_default("heuristic.page_list", "config/pages.toml")?;
+    Ok(builder)
+}
+
+/// `OD_*` environment variables, `__` separates nested keys
+fn env_source() -> Environment {
+    Environment::with_prefix(ENV_PREFIX)
+        .prefix_separator("_")
+        .separator(ENV_SEPARATOR)
+}
+
+/// Build config from layers, later ones take precedence:
+/// built-in defaults, system file, user file, project file, `OD_*` env vars, cli flags.
+pub fn build_config(cli: &cli_config::CliConfig) -> Result<Config> {
+    let mut builder = with_defaults(Config::builder())?;
+
+    builder = builder.add_source(file_source(SYSTEM_CONFIG_FILENAME, false));
+    if let Some(fpath) = get_user_config_fpath() {
+        builder = builder.add_source(file_source(&fpath.to_string_lossy(), false));
+    }
+    builder = match cli.config.as_ref() {
+        Some(fpath) => builder.add_source(file_source(fpath, true)),
+        None => builder.add_source(file_source(CONFIG_FILENAME, false)),
+    };
+
+    builder = builder.add_source(env_source());
+
+    builder = builder.set_override_option("download.base_dir", cli.base_dir.clone())?;
+    builder = builder.set_override_option("heuristic.enabled", cli.no_llm.then_some(true))?;
+
+    let config = builder.build()?;
+    Ok(config)
+}
+
+/// Config from a toml string on top of the built-in defaults, without files, env or cli.
+/// Meant for embedding the crate, e.g. in tests or another service.
+pub fn load_config_from_str(toml_str: &str) -> Result<AppConfig> {
+    let config = with_defaults(Config::builder())?
+        .add_source(File::from_str(toml_str, FileFormat::Toml))
+        .build()?;
+    let app_config = config.try_deserialize()?;
+    Ok(app_config)
+}
+
+/// Build and validate config, failing with every problem found.
+pub fn load_config(cli: &cli_config::CliConfig) -> Result<AppConfig> {
+    let config = build_config(cli)?;
+    let issues = validate(&config);
+    if !issues.is_empty() {
+        let report: Vec<String> = issues.iter().map(|it| format!("  {it}")).collect();
+        bail!("Invalid config:\n{}", report.join("\n"));
+    }
+    let app_config = config.try_deserialize()?;
+    Ok(app_config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_override() -> Result<()> {
+        let cfg = Config::builder()
+            .set_default("download.base_dir", "/from/default")?
+            .add_source(env_source().source(Some(
+                [("OD_DOWNLOAD__BASE_DIR".to_string(), "/from/env".to_string())].into(),
+            )))
+            .build()?;
+        assert_eq!(cfg.get_string("download.base_dir")?, "/from/env");
+        Ok(())
+    }
+
+    #[test]
+    fn test_layer_precedence() -> Result<()> {
+        let cfg = with_defaults(Config::builder())?
+            .add_source(File::from_str(
+                r#"
+[api]
+key = "file-key"
+api_url = "http://localhost"
+retry = 3
+
+[download]
+username = "user"
+base_dir = "/from/file"
+"#,
+                FileFormat::Toml,
+            ))
+            .add_source(env_source().source(Some(
+                [("OD_DOWNLOAD__BASE_DIR".to_string(), "/from/env".to_string())].into(),
+            )))
+            .set_override_option("download.base_dir", Some("/from/cli"))?
+            .build()?;
+        let app: AppConfig = cfg.try_deserialize()?;
+
+        assert_eq!(app.download.base_dir, "/from/cli");
+        assert_eq!(app.download.username, "user");
+        assert_eq!(app.download.max_version_count, 10);
+        Ok(())
+    }
+}
@@ -0,0 +1,340 @@
+use std::fmt::Display;
+use std::path::Path;
+
+use config::{Config, Value};
+use reqwest::{Url, header::HeaderValue};
+use serde::Serialize;
+
+use super::{components::CompOverrides, file_config::AppConfig};
+use crate::{
+    download::download_page::{policy::forge_names, registry::RegistryEntry},
+    llm_api::{config::ProviderKind, prompts::Prompts},
+};
+
+/// origin reported for values without a source file, i.e. defaults and cli flags
+const UNKNOWN_ORIGIN: &str = "<default or command line>";
+
+/// a single problem found in the loaded config
+#[derive(Debug, Serialize)]
+pub struct ConfigIssue {
+    pub key: String,
+    /// file or env the offending value comes from
+    pub origin: Option<String>,
+    pub msg: String,
+}
+
+impl Display for ConfigIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let origin = self.origin.as_deref().unwrap_or(UNKNOWN_ORIGIN);
+        write!(f, "{}: `{}` {}", origin, self.key, self.msg)
+    }
+}
+
+struct Checker<'a> {
+    config: &'a Config,
+    issues: Vec<ConfigIssue>,
+}
+
+impl Checker<'_> {
+    fn push(&mut self, key: &str, val: Option<&Value>, msg: String) {
+        self.issues.push(ConfigIssue {
+            key: key.to_string(),
+            origin: val.and_then(|v| v.origin()).map(str::to_string),
+            msg,
+        });
+    }
+
+    /// look up a dotted `section.field` key, keeping the origin of the value
+    fn lookup(&mut self, key: &str) -> Option<Value> {
+        let (section, field) = key.rsplit_once('.').unwrap_or(("", key));
+        let val = self
+            .config
+            .get_table(section)
+            .ok()
+            .and_then(|mut tbl| tbl.remove(field));
+        if val.is_none() {
+            self.push(key, None, "is missing".to_string());
+        }
+        val
+    }
+
+    fn check_string(&mut self, key: &str) -> Option<(String, Value)> {
+        let val = self.lookup(key)?;
+        match val.clone().into_string() {
+            Ok(s) if s.trim().is_empty() => {
+                self.push(key, Some(&val), "is empty".to_string());
+                None
+            }
+            Ok(s) => Some((s, val)),
+            Err(e) => {
+                self.push(key, Some(&val), format!("is not a string: {e}"));
+                None
+            }
+        }
+    }
+
+    fn check_url(&mut self, key: &str) {
+        let Some((s, val)) = self.check_string(key) else {
+            return;
+        };
+        match Url::parse(&s) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+            Ok(url) => self.push(
+                key,
+                Some(&val),
+                format!("has unsupported scheme `{}`: {s}", url.scheme()),
+            ),
+            Err(e) => self.push(key, Some(&val), format!("is not a valid url ({e}): {s}")),
+        }
+    }
+
+    fn check_min_int(&mut self, key: &str, min: i64) {
+        let Some(val) = self.lookup(key) else {
+            return;
+        };
+        match val.clone().into_int() {
+            Ok(n) if n < min => self.push(key, Some(&val), format!("must be >= {min}, got {n}")),
+            Ok(_) => {}
+            Err(e) => self.push(key, Some(&val), format!("is not an integer: {e}")),
+        }
+    }
+
+    fn check_float_range(&mut self, key: &str, lo: f64, hi: f64) {
+        let Some(val) = self.lookup(key) else {
+            return;
+        };
+        match val.clone().into_float() {
+            Ok(x) if !(lo..=hi).contains(&x) => self.push(
+                key,
+                Some(&val),
+                format!("must be within {lo}..={hi}, got {x}"),
+            ),
+            Ok(_) => {}
+            Err(e) => self.push(key, Some(&val), format!("is not a number: {e}")),
+        }
+    }
+
+    fn check_provider(&mut self) -> Option<String> {
+        let key = "api.provider";
+        let (s, val) = self.check_string(key)?;
+        if !ProviderKind::NAMES.contains(&s.as_str()) {
+            self.push(
+                key,
+                Some(&val),
+                format!("must be one of {:?}, got {s:?}", ProviderKind::NAMES),
+            );
+            return None;
+        }
+        Some(s)
+    }
+
+    /// key may be left empty for a local ollama server
+    fn check_api_key(&mut self, provider: Option<&str>) {
+        let key = "api.key";
+        let s_val = if provider == Some("ollama") {
+            self.lookup(key)
+                .and_then(|val| Some((val.clone().into_string().ok()?, val)))
+        } else {
+            self.check_string(key)
+        };
+        let Some((s, val)) = s_val else {
+            return;
+        };
+        if HeaderValue::from_str(&format!("Bearer {s}")).is_err() {
+            self.push(
+                key,
+                Some(&val),
+                "contains characters not allowed in an http header".to_string(),
+            );
+        }
+    }
+
+    /// base dir must contain `<username>/Official` and `<username>/GitHub`
+    fn check_dl_dirs(&mut self) {
+        let uname_op = self.check_string("download.username");
+        let Some((base_dir, val)) = self.check_string("download.base_dir") else {
+            return;
+        };
+        let key = "download.base_dir";
+        let base = Path::new(&base_dir);
+        if !base.is_dir() {
+            self.push(key, Some(&val), format!("{base_dir} is not a directory"));
+            return;
+        }
+        let Some((uname, _)) = uname_op else {
+            return;
+        };
+        for sub in ["Official", "GitHub"] {
+            let dir = base.join(&uname).join(sub);
+            if !dir.is_dir() {
+                self.push(
+                    key,
+                    Some(&val),
+                    format!(
+                        "is missing subdirectory {:?}, please create it manually",
+                        dir
+                    ),
+                );
+            }
+        }
+    }
+
+    fn check_components_file(&mut self) {
+        let key = "download.components_file";
+        let Some((fpath, val)) = self.check_string(key) else {
+            return;
+        };
+        if let Err(e) = CompOverrides::load(Path::new(&fpath)) {
+            self.push(key, Some(&val), format!("{:#}", e));
+        }
+    }
+
+    fn check_registry(&mut self) {
+        let key = "registry.hosts";
+        let Ok(val) = self.config.get::<Value>(key) else {
+            return;
+        };
+        let entries: Vec<RegistryEntry> = match val.clone().try_deserialize() {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.push(key, Some(&val), format!("is not a list of entries: {e}"));
+                return;
+            }
+        };
+        for entry in entries.iter() {
+            if let Err(e) = entry.check() {
+                self.push(key, Some(&val), format!("{:#}", e));
+            }
+        }
+    }
+
+    fn check_forges(&mut self) {
+        let key = "policy.deny_forges";
+        let Ok(val) = self.config.get::<Value>(key) else {
+            return;
+        };
+        let names: Vec<String> = match val.clone().try_deserialize() {
+            Ok(names) => names,
+            Err(e) => {
+                self.push(key, Some(&val), format!("is not a list of names: {e}"));
+                return;
+            }
+        };
+        let known = forge_names();
+        for name in names.iter() {
+            if !known.iter().any(|it| it.eq_ignore_ascii_case(name)) {
+                self.push(
+                    key,
+                    Some(&val),
+                    format!("unknown forge {name:?}, must be one of {known:?}"),
+                );
+            }
+        }
+    }
+
+    fn check_prompts_dir(&mut self) {
+        let key = "prompts.dir";
+        let Some((dir, val)) = self.check_string(key) else {
+            return;
+        };
+        if let Err(e) = Prompts::load(Path::new(&dir)) {
+            self.push(key, Some(&val), format!("{:#}", e));
+        }
+    }
+}
+
+/// Check the built config and report every problem found, without any network I/O.
+pub fn validate(config: &Config) -> Vec<ConfigIssue> {
+    let mut checker = Checker {
+        config,
+        issues: vec![],
+    };
+
+    // the llm api is never called in heuristic mode
+    if !config.get_bool("heuristic.enabled").unwrap_or(false) {
+        let provider = checker.check_provider();
+        checker.check_api_key(provider.as_deref());
+        checker.check_url("api.api_url");
+        checker.check_string("api.model_id");
+    }
+    checker.check_float_range("api.temperature", 0.0, 2.0);
+    checker.check_min_int("api.max_tokens", 1);
+    checker.check_min_int("api.parallel", 1);
+    checker.check_min_int("api.retry", 1);
+    checker.check_min_int("api.check_retry", 1);
+    checker.check_min_int("api.retry_delay", 0);
+    checker.check_min_int("api.max_retry_delay", 0);
+    checker.check_min_int("api.few_shot", 0);
+    checker.check_min_int("api.link_batch_size", 0);
+    checker.check_min_int("api.rpm", 0);
+    checker.check_min_int("api.tpm", 0);
+    checker.check_min_int("api.time_out", 1);
+    checker.check_min_int("api.llm_time_out", 1);
+    checker.check_min_int("download.max_version_count", 1);
+    checker.check_dl_dirs();
+    checker.check_components_file();
+    checker.check_min_int("cache.ttl", 0);
+    checker.check_prompts_dir();
+    checker.check_min_int("vote.samples", 1);
+    checker.check_float_range("vote.temperature", 0.0, 2.0);
+    checker.check_float_range("vote.min_agreement", 0.0, 1.0);
+    checker.check_registry();
+    checker.check_forges();
+    checker.check_min_int("agent.max_tool_calls", 1);
+    checker.check_min_int("agent.max_page_chars", 1);
+
+    // anything the field checks above do not cover, e.g. type mismatches
+    if checker.issues.is_empty()
+        && let Err(e) = config.clone().try_deserialize::<AppConfig>()
+    {
+        checker.push("", None, e.to_string());
+    }
+    checker.issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use color_eyre::eyre::Result;
+    use config::{File, FileFormat};
+
+    #[test]
+    fn test_validate_reports_all() -> Result<()> {
+        let cfg = crate::config::with_defaults(Config::builder())?
+            .add_source(File::from_str(
+                r#"
+[api]
+key = "xxx"
+temperature = 3.0
+parallel = 0
+
+[download]
+username = "nobody"
+base_dir = "/nonexistent/official_download"
+components_file = "/nonexistent/components.toml"
+
+[registry]
+hosts = [{ url = "https://a.org/dist/" }]
+
+[policy]
+deny_forges = ["GitHub", "Gitea"]
+"#,
+                FileFormat::Toml,
+            ))
+            .build()?;
+        let issues = validate(&cfg);
+        let keys: Vec<&str> = issues.iter().map(|it| it.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            [
+                "api.api_url",
+                "api.temperature",
+                "api.parallel",
+                "download.base_dir",
+                "registry.hosts",
+                "policy.deny_forges"
+            ]
+        );
+        Ok(())
+    }
+}
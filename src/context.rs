@@ -0,0 +1,255 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, OnceLock},
+};
+
+use color_eyre::eyre::Result;
+
+use crate::{
+    audit::{AuditEvent, AuditLog},
+    cassette::Cassette,
+    config::{
+        cli_config::CliConfig,
+        components::{CompOverride, CompOverrides},
+        file_config::{AppConfig, HeuristicConfig, PolicyConfig, RegistryConfig, VerifyConfig},
+        load_config,
+    },
+    download::{
+        download_page::{
+            few_shot::{VerifiedPage, load_verified_pages},
+            heuristic::load_page_list,
+            policy::{PolicyVerdict, judge_url},
+        },
+        file_download::path::get_official_available_page_fpath,
+    },
+    llm_api::{
+        cache::{CacheMode, LlmCache},
+        config::{AgentConfig, ApiConfig, VoteConfig},
+        prompts::Prompts,
+        provider::{LlmProvider, construct_provider},
+        usage::UsageLedger,
+    },
+};
+
+/// Everything a pipeline run needs, passed explicitly instead of read from globals.
+/// Cheap to clone, so each spawned worker can own a copy.
+#[derive(Clone)]
+pub struct Context {
+    config: Arc<AppConfig>,
+    comp_overrides: Arc<CompOverrides>,
+    /// user supplied download pages of `heuristic.page_list`
+    page_list: Arc<HashMap<String, String>>,
+    llm: Arc<dyn LlmProvider>,
+    llm_cache: Arc<LlmCache>,
+    usage: Arc<UsageLedger>,
+    prompts: Arc<Prompts>,
+    verified_pages: Arc<OnceLock<Vec<VerifiedPage>>>,
+    cassette: Arc<Cassette>,
+    audit: Arc<AuditLog>,
+}
+
+impl Context {
+    pub fn new(config: AppConfig) -> Result<Self> {
+        let llm = construct_provider(&config.api)
+            .map_err(|e| eyre::eyre!("Failed to construct llm provider, check `api.key`: {e}"))?;
+        let comp_overrides = CompOverrides::load(Path::new(&config.download.components_file))?;
+        let page_list = load_page_list(Path::new(&config.heuristic.page_list))?;
+        let llm_cache = LlmCache::new(
+            &config.cache,
+            &config.download.base_dir,
+            CacheMode::ReadWrite,
+        );
+        let usage = UsageLedger::new(config.api.get_prices().clone());
+        let prompts = Prompts::load(Path::new(&config.prompts.dir))?;
+        let audit_dir = Path::new(&config.download.base_dir)
+            .join(&config.download.username)
+            .join("Official");
+        let audit = AuditLog::new(config.download.audit, audit_dir);
+        Ok(Self {
+            config: Arc::new(config),
+            comp_overrides: Arc::new(comp_overrides),
+            page_list: Arc::new(page_list),
+            llm,
+            llm_cache: Arc::new(llm_cache),
+            usage: Arc::new(usage),
+            prompts: Arc::new(prompts),
+            verified_pages: Arc::new(OnceLock::new()),
+            cassette: Arc::new(Cassette::off()),
+            audit: Arc::new(audit),
+        })
+    }
+
+    /// load and validate config from cli flags and the config layers
+    pub fn from_cli(cli: &CliConfig) -> Result<Self> {
+        let config = load_config(cli)?;
+        let mode = if cli.no_cache {
+            CacheMode::Off
+        } else if cli.refresh {
+            CacheMode::Refresh
+        } else {
+            CacheMode::ReadWrite
+        };
+        let ctx = Self::new(config)?.with_cache_mode(mode);
+        let cassette = match (cli.record.as_ref(), cli.replay.as_ref()) {
+            (Some(fpath), _) => Cassette::record(fpath)?,
+            (_, Some(fpath)) => Cassette::replay(fpath)?,
+            _ => Cassette::off(),
+        };
+        Ok(ctx.with_cassette(cassette))
+    }
+
+    pub fn with_cassette(mut self, cassette: Cassette) -> Self {
+        self.cassette = Arc::new(cassette);
+        self
+    }
+
+    pub fn with_cache_mode(mut self, mode: CacheMode) -> Self {
+        let cfg = &self.config;
+        self.llm_cache = Arc::new(LlmCache::new(&cfg.cache, &cfg.download.base_dir, mode));
+        self
+    }
+
+    pub fn config(&self) -> &AppConfig {
+        &self.config
+    }
+
+    pub fn api(&self) -> &ApiConfig {
+        &self.config.api
+    }
+
+    pub fn vote(&self) -> &VoteConfig {
+        &self.config.vote
+    }
+
+    pub fn agent(&self) -> &AgentConfig {
+        &self.config.agent
+    }
+
+    pub fn policy(&self) -> &PolicyConfig {
+        &self.config.policy
+    }
+
+    /// whether `url` counts as an official download page of the component
+    pub fn judge_url(&self, comp_name: &str, url: &str) -> PolicyVerdict {
+        judge_url(&self.config.policy, self.comp_override(comp_name), url)
+    }
+
+    pub fn verify(&self) -> &VerifyConfig {
+        &self.config.verify
+    }
+
+    pub fn registry(&self) -> &RegistryConfig {
+        &self.config.registry
+    }
+
+    pub fn heuristic(&self) -> &HeuristicConfig {
+        &self.config.heuristic
+    }
+
+    /// whether llm calls are disabled (`--no-llm`)
+    pub fn no_llm(&self) -> bool {
+        self.config.heuristic.enabled
+    }
+
+    /// download page of the component in `heuristic.page_list`
+    pub fn listed_page(&self, comp_name: &str) -> Option<&str> {
+        self.page_list.get(comp_name).map(String::as_str)
+    }
+
+    pub fn get_ver_cnt(&self) -> usize {
+        self.config.download.max_version_count
+    }
+
+    /// manual override for the component, if any
+    pub fn comp_override(&self, comp_name: &str) -> Option<&CompOverride> {
+        self.comp_overrides.get(comp_name)
+    }
+
+    /// version count of the component, honoring its override
+    pub fn get_comp_ver_cnt(&self, comp_name: &str) -> usize {
+        self.comp_override(comp_name)
+            .and_then(|ovr| ovr.max_version_count)
+            .unwrap_or_else(|| self.get_ver_cnt())
+    }
+
+    pub fn get_username(&self) -> &str {
+        &self.config.download.username
+    }
+
+    pub fn get_dl_base_dir(&self) -> &str {
+        &self.config.download.base_dir
+    }
+
+    pub fn llm(&self) -> &dyn LlmProvider {
+        self.llm.as_ref()
+    }
+
+    pub fn llm_cache(&self) -> &LlmCache {
+        &self.llm_cache
+    }
+
+    pub fn prompts(&self) -> &Prompts {
+        &self.prompts
+    }
+
+    /// download pages confirmed by earlier runs, loaded on first use
+    pub fn verified_pages(&self) -> &[VerifiedPage] {
+        self.verified_pages.get_or_init(|| {
+            let res = get_official_available_page_fpath(self)
+                .and_then(|fpath| load_verified_pages(&fpath));
+            res.unwrap_or_else(|e| {
+                log::warn!("No verified pages for few-shot examples: {}", e);
+                vec![]
+            })
+        })
+    }
+
+    /// token usage of all llm calls made through this context and its clones
+    pub fn usage(&self) -> &UsageLedger {
+        &self.usage
+    }
+
+    /// append to the audit trail of the component
+    pub fn audit(&self, comp_name: &str, event: AuditEvent) {
+        self.audit.record(comp_name, &event);
+    }
+
+    /// recorder or player of outgoing traffic
+    pub fn cassette(&self) -> &Cassette {
+        &self.cassette
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_config_from_str;
+
+    fn ctx_from_toml(base_dir: &str, model_id: &str) -> Result<Context> {
+        let toml_str = format!(
+            r#"
+[api]
+api_url = "http://localhost:8080"
+model_id = "{model_id}"
+
+[download]
+username = "user"
+base_dir = "{base_dir}"
+components_file = "/nonexistent/components.toml"
+"#
+        );
+        Context::new(load_config_from_str(&toml_str)?)
+    }
+
+    #[test]
+    fn test_independent_contexts() -> Result<()> {
+        let ctx_a = ctx_from_toml("/tmp/a", "model-a")?;
+        let ctx_b = ctx_from_toml("/tmp/b", "model-b")?;
+        assert_eq!(ctx_a.get_dl_base_dir(), "/tmp/a");
+        assert_eq!(ctx_b.get_dl_base_dir(), "/tmp/b");
+        assert_eq!(ctx_a.api().get_model_id(), "model-a");
+        assert_eq!(ctx_b.clone().api().get_model_id(), "model-b");
+        Ok(())
+    }
+}
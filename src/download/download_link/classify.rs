@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use color_eyre::eyre::Result;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::entities::PageLink;
+use crate::{
+    context::Context,
+    llm_api::{
+        get_llm_structured,
+        prompts::PromptRef,
+        usage::{CallMeta, Stage},
+    },
+};
+
+/// verdict of the model on a single link
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct LinkVerdict {
+    /// id of the link in the prompt
+    pub id: usize,
+    pub url: String,
+    /// points to a compressed package containing source code
+    pub is_source_archive: bool,
+    /// belongs to the component itself
+    pub is_related: bool,
+    /// short explanation
+    pub reason: String,
+}
+
+impl LinkVerdict {
+    pub fn is_accepted(&self) -> bool {
+        self.is_source_archive && self.is_related
+    }
+}
+
+/// answer to the batched classification prompt
+#[derive(Debug, Deserialize, JsonSchema)]
+struct LinkVerdicts {
+    verdicts: Vec<LinkVerdict>,
+}
+
+/// Classify a chunk of links in a single llm call, keyed by url.
+/// Links the model leaves out get no verdict.
+pub async fn classify_links(
+    ctx: &Context,
+    comp_name: &str,
+    links: &[PageLink],
+) -> Result<(HashMap<String, LinkVerdict>, PromptRef)> {
+    let items: Vec<_> = links
+        .iter()
+        .enumerate()
+        .map(|(id, link)| json!({"id": id, "url": link.url, "text": link.text}))
+        .collect();
+    let data = json!({
+        "comp_name": comp_name,
+        "links": items,
+    });
+    let (prmp, prompt) = ctx.prompts().render("link_batch", &data)?;
+    let meta = CallMeta::new(comp_name, Stage::LinkClassification);
+    let ans: LinkVerdicts = get_llm_structured(ctx, &meta, &prmp).await?;
+
+    let mut res = HashMap::new();
+    for verdict in ans.verdicts {
+        // trust the echoed url, fall back to the id if the model mangled it
+        let link = links
+            .iter()
+            .find(|link| link.url == verdict.url)
+            .or_else(|| links.get(verdict.id));
+        match link {
+            Some(link) => {
+                res.insert(link.url.clone(), verdict);
+            }
+            None => log::warn!("verdict for unknown link {}", verdict.url),
+        }
+    }
+    if res.len() < links.len() {
+        log::warn!(
+            "{} of {} links of {} left unclassified",
+            links.len() - res.len(),
+            links.len(),
+            comp_name
+        );
+    }
+    Ok((res, prompt))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm_api::prompts::Prompts;
+
+    #[test]
+    fn test_batch_prompt() -> Result<()> {
+        let data = json!({
+            "comp_name": "wget",
+            "links": [
+                {"id": 0, "url": "https://ftp.gnu.org/gnu/wget/wget-1.21.tar.gz?a=1&b=2", "text": "wget-1.21.tar.gz"},
+                {"id": 1, "url": "https://ftp.gnu.org/gnu/", "text": ""},
+            ],
+        });
+        let (prmp, _) = Prompts::builtin().render("link_batch", &data)?;
+        assert!(prmp.contains(
+            "0. https://ftp.gnu.org/gnu/wget/wget-1.21.tar.gz?a=1&b=2 (anchor text: wget-1.21.tar.gz)"
+        ));
+        assert!(prmp.contains("1. https://ftp.gnu.org/gnu/\n"));
+        Ok(())
+    }
+}
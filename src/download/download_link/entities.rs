@@ -1,13 +1,28 @@
 use color_eyre::eyre::Result;
 use regex::Regex;
 use reqwest::Url;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+use crate::llm_api::prompts::PromptRef;
+
+/// an `<a>` element of a download page
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PageLink {
+    /// absolute url of the href
+    pub url: String,
+    /// anchor text, whitespace collapsed
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct DLEntry {
     pub url: String,
     pub fname: String,
     pub fname_ext: String,
     pub comp_name: String,
+    /// prompt templates that accepted the link
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prompts: Vec<PromptRef>,
 }
 
 impl DLEntry {
@@ -16,39 +31,42 @@ impl DLEntry {
         seg_iter.next_back()
     }
 
-    fn get_entry_from_fname(fname: &str, url: &str, comp_name: &str) -> Result<Option<Self>> {
-        let ver_op = Self::get_version_from_fname(fname)?;
-        if ver_op.is_none() {
-            return Ok(None);
-        }
-
-        let ver = ver_op.unwrap();
-        Ok(Some(Self {
+    fn get_entry_from_fname(
+        fname: &str,
+        url: &str,
+        comp_name: &str,
+        ver_re: &Regex,
+    ) -> Option<Self> {
+        let ver_op = Self::get_version_str_from_match(ver_re, fname);
+        let ver = ver_op?;
+        Some(Self {
             url: url.to_string(),
             fname: format!("{}-{}", comp_name, ver),
             fname_ext: fname.to_string(),
             comp_name: comp_name.to_string(),
-        }))
+            prompts: vec![],
+        })
     }
 
+    /// capture group named `ver` if the regex has one, otherwise the whole match
     fn get_version_str_from_match(re: &Regex, fname: &str) -> Option<String> {
-        let mat = re.find(fname)?;
+        let caps = re.captures(fname)?;
+        let mat = caps.name("ver").or_else(|| caps.get(0))?;
         Some(mat.as_str().to_string())
     }
 
-    fn get_version_from_fname(fname: &str) -> Result<Option<String>> {
+    pub fn from_url(url: &str, comp_name: &str) -> Result<Option<Self>> {
         let re = Regex::new(r"\d+(\.\d+([[:alnum:]])?)")?;
-        let res_op = Self::get_version_str_from_match(&re, fname);
-        Ok(res_op)
+        Self::from_url_with_regex(url, comp_name, &re)
     }
 
-    pub fn from_url(url: &str, comp_name: &str) -> Result<Option<Self>> {
+    /// like [`DLEntry::from_url`], with a custom version regex
+    pub fn from_url_with_regex(url: &str, comp_name: &str, ver_re: &Regex) -> Result<Option<Self>> {
         let url_par = Url::parse(url)?;
         match Self::get_last_path(&url_par) {
-            Some(ext_fname) => {
-                let ent_op = Self::get_entry_from_fname(ext_fname, url, comp_name)?;
-                Ok(ent_op)
-            }
+            Some(ext_fname) => Ok(Self::get_entry_from_fname(
+                ext_fname, url, comp_name, ver_re,
+            )),
             None => Ok(None),
         }
     }
@@ -0,0 +1,74 @@
+use reqwest::Url;
+
+/// file name suffixes of source archives
+const ARCHIVE_EXTS: [&str; 12] = [
+    ".tar.gz",
+    ".tgz",
+    ".tar.bz2",
+    ".tbz2",
+    ".tar.xz",
+    ".txz",
+    ".tar.lz",
+    ".tar.lzma",
+    ".tar.zst",
+    ".tar.z",
+    ".tar",
+    ".zip",
+];
+
+pub(crate) fn file_name(url: &str) -> Option<String> {
+    let url_par = Url::parse(url).ok()?;
+    let fname = url_par.path_segments()?.next_back()?;
+    (!fname.is_empty()).then(|| fname.to_string())
+}
+
+pub(crate) fn is_archive_name(fname: &str) -> bool {
+    let fname = fname.to_lowercase();
+    ARCHIVE_EXTS.iter().any(|ext| fname.ends_with(ext))
+}
+
+/// the file name starts with the component name and a separator, `wget2-2.1` is no file of `wget`
+fn matches_comp_name(fname: &str, comp_name: &str) -> bool {
+    let fname = fname.to_lowercase();
+    let Some(rest) = fname.strip_prefix(&comp_name.to_lowercase()) else {
+        return false;
+    };
+    rest.starts_with(['-', '_', '.'])
+}
+
+/// Source archive of the component judged by its file name alone, no llm involved.
+pub fn is_heuristic_source(url: &str, comp_name: &str) -> bool {
+    file_name(url)
+        .is_some_and(|fname| is_archive_name(&fname) && matches_comp_name(&fname, comp_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heuristic_source() {
+        let base = "https://ftp.gnu.org/gnu/wget/";
+        assert!(is_heuristic_source(
+            &format!("{base}wget-1.21.tar.gz"),
+            "wget"
+        ));
+        assert!(is_heuristic_source(
+            &format!("{base}Wget2_2.1.zip"),
+            "wget2"
+        ));
+        assert!(!is_heuristic_source(
+            &format!("{base}wget-1.21.tar.gz.sig"),
+            "wget"
+        ));
+        assert!(!is_heuristic_source(
+            &format!("{base}wget2-2.1.tar.gz"),
+            "wget"
+        ));
+        assert!(!is_heuristic_source(
+            &format!("{base}gnutls-3.8.tar.xz"),
+            "wget"
+        ));
+        assert!(!is_heuristic_source(base, "wget"));
+    }
+}
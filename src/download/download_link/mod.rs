@@ -1,39 +1,52 @@
-use std::{str::FromStr, sync::Arc};
+use std::{collections::HashSet, str::FromStr, sync::Arc};
 
 use crate::{
-    config::get_ver_cnt,
+    audit::{AuditEvent, RejectReason},
+    cassette::Exchange,
+    context::Context,
     llm_api::{
-        config::{get_api_retry, get_api_retry_delay},
         get_llm_completion,
+        prompts::PromptRef,
+        usage::{CallMeta, Stage},
     },
-    utils::{construct_semaphore, get_with_retry, is_absolute_url, is_url_accessible},
+    utils::{RetryPolicy, construct_semaphore, get_with_retry, is_absolute_url, is_url_accessible},
 };
+use classify::classify_links;
 use color_eyre::eyre::Result;
-use entities::DLEntry;
-use handlebars::Handlebars;
-use reqwest::{Client, Url};
+use entities::{DLEntry, PageLink};
+use eyre::bail;
+use heuristic::is_heuristic_source;
+use reqwest::{Client, Response, StatusCode, Url};
 use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use tokio::sync::Semaphore;
 
 use super::download_page::entities::PageAns;
 
+pub mod classify;
 pub mod entities;
-
-async fn is_url_related_to_comp(url: &str, comp_name: &str) -> Result<bool> {
-    let prompt_tempalate = r#"
-Is the download url {{url}} related to the opensource component {{comp_name}}?
-Please reply with a simple yes or no.
-        "#;
-    let reg = Handlebars::new();
+pub mod heuristic;
+
+/// along with the prompt template asked
+async fn is_url_related_to_comp(
+    ctx: &Context,
+    url: &str,
+    comp_name: &str,
+) -> Result<(bool, PromptRef)> {
     let data = json!({
     "comp_name": comp_name,
     "url":url,
     });
-    let prmp = reg.render_template(prompt_tempalate, &data)?;
-    let ans = get_llm_completion(&prmp).await?;
+    let (prmp, prompt) = ctx.prompts().render("link_related", &data)?;
+    let meta = CallMeta::new(comp_name, Stage::RelatednessCheck);
+    let ans = get_llm_completion(ctx, &meta, &prmp).await?;
 
     let flag = ans.to_lowercase().contains("yes");
+    ctx.audit(
+        comp_name,
+        AuditEvent::verdict(Stage::RelatednessCheck, Some(url), &flag),
+    );
 
     #[cfg(debug_assertions)]
     {
@@ -41,15 +54,50 @@ Please reply with a simple yes or no.
             log::warn!("opencv link related check: {}, ans: {}", url, flag);
         }
     }
-    Ok(flag)
+    Ok((flag, prompt))
+}
+
+/// Body of the page, an error status fails instead of returning the error page.
+pub(crate) async fn get_page_content(ctx: &Context, page_url: &str) -> Result<String> {
+    fetch_page_content(ctx, page_url, &Client::new(), |_| None).await
 }
 
-async fn get_page_content(page_url: &str) -> Result<String> {
-    let cli = Client::new();
+/// [`get_page_content`] with the client `cli`, `refuse` may turn down the answer before
+/// its body is read, e.g. for the url it was redirected to
+pub(crate) async fn fetch_page_content(
+    ctx: &Context,
+    page_url: &str,
+    cli: &Client,
+    refuse: impl Fn(&Response) -> Option<String>,
+) -> Result<String> {
+    let cassette = ctx.cassette();
+    if cassette.is_replay() {
+        let Exchange::Get { status, body, .. } = cassette.take("get", page_url)? else {
+            unreachable!("exchanges are looked up by kind");
+        };
+        return page_body(page_url, status, body);
+    }
+    let api = ctx.api();
     log::info!("fetch content for page {}", page_url);
-    let resp = get_with_retry(&cli, page_url, get_api_retry(), get_api_retry_delay()).await?;
+    let resp = get_with_retry(cli, page_url, &RetryPolicy::from_api(api)).await?;
+    if let Some(reason) = refuse(&resp) {
+        bail!("refused to read {}: {}", resp.url(), reason);
+    }
+    let status = resp.status().as_u16();
     let text = resp.text().await?;
-    Ok(text)
+    cassette.put(&Exchange::Get {
+        url: page_url.to_string(),
+        status,
+        body: text.clone(),
+    });
+    page_body(page_url, status, text)
+}
+
+fn page_body(page_url: &str, status: u16, body: String) -> Result<String> {
+    if !StatusCode::from_u16(status).is_ok_and(|it| it.is_success()) {
+        bail!("page {} answered with status {}", page_url, status);
+    }
+    Ok(body)
 }
 
 fn transform_href(href: &str, url: &str) -> Result<String> {
@@ -73,7 +121,14 @@ fn transform_href(href: &str, url: &str) -> Result<String> {
     Ok(link)
 }
 
-fn get_all_links(page_content: &str, url: &str) -> Result<Vec<String>> {
+/// anchor text with whitespace collapsed, cut to a prompt friendly length
+fn anchor_text<'a>(texts: impl Iterator<Item = &'a str>) -> String {
+    let text = texts.collect::<Vec<_>>().join(" ");
+    let words: Vec<&str> = text.split_whitespace().collect();
+    words.join(" ").chars().take(80).collect()
+}
+
+pub(crate) fn get_all_links(page_content: &str, url: &str) -> Result<Vec<PageLink>> {
     log::info!("start to get links for page {}", url);
     let doc = Html::parse_document(page_content);
     let sltr = Selector::parse("a").unwrap_or_else(|e| {
@@ -84,7 +139,10 @@ fn get_all_links(page_content: &str, url: &str) -> Result<Vec<String>> {
     for a_ele in doc.select(&sltr) {
         if let Some(href) = a_ele.value().attr("href") {
             let url = transform_href(href, url)?;
-            link_list.push(url);
+            link_list.push(PageLink {
+                url,
+                text: anchor_text(a_ele.text()),
+            });
         }
     }
     log::info!("{} links extracted from page {}", link_list.len(), url);
@@ -92,6 +150,7 @@ fn get_all_links(page_content: &str, url: &str) -> Result<Vec<String>> {
     Ok(link_list)
 }
 
+#[derive(Deserialize, Serialize)]
 pub struct DLEntryPool {
     pub comp_name: String,
     pub entries: Vec<DLEntry>,
@@ -123,34 +182,54 @@ impl DLEntryPool {
         true
     }
 
-    async fn is_source_link(url: &str, comp_name: &str) -> Result<bool> {
+    /// checks before asking the llm: signatures, executables and dead links
+    async fn is_link_candidate(ctx: &Context, url: &str, comp_name: &str) -> bool {
         if url.contains(".sig") || url.contains(".exe") {
-            return Ok(false);
+            ctx.audit(comp_name, AuditEvent::rejected(url, RejectReason::Filtered));
+            return false;
         }
 
-        let (flag, _) = is_url_accessible(url).await;
-        if !flag {
+        let access = is_url_accessible(ctx, url).await;
+        if !access.is_accessible() {
+            let detail = access.describe();
             log::warn!(
-                "extracted link {} for component {} is not accessible",
+                "extracted link {} for component {} is not accessible: {}",
                 url,
-                comp_name
+                comp_name,
+                detail
             );
-            return Ok(false);
+            ctx.audit(
+                comp_name,
+                AuditEvent::rejected_for(url, RejectReason::Inaccessible, &detail),
+            );
+            return false;
+        }
+        true
+    }
+
+    /// along with the prompt template, none if the link was dropped before asking
+    async fn is_source_link(
+        ctx: &Context,
+        url: &str,
+        comp_name: &str,
+    ) -> Result<(bool, Option<PromptRef>)> {
+        if !Self::is_link_candidate(ctx, url, comp_name).await {
+            return Ok((false, None));
         }
 
-        let prompt_tempalate = r#"
-Does the URL {{url}} point to a compressed package containing the source code of the open-source component {{component}}?
-Please reply with a simple 'yes' or 'no'.
-        "#;
-        let reg = Handlebars::new();
         let data = json!({
         "comp_name": comp_name,
         "url":url,
         });
-        let prmp = reg.render_template(prompt_tempalate, &data)?;
-        let ans = get_llm_completion(&prmp).await?;
+        let (prmp, prompt) = ctx.prompts().render("source_link", &data)?;
+        let meta = CallMeta::new(comp_name, Stage::SourceCheck);
+        let ans = get_llm_completion(ctx, &meta, &prmp).await?;
 
         let flag = ans.to_lowercase().contains("yes");
+        ctx.audit(
+            comp_name,
+            AuditEvent::verdict(Stage::SourceCheck, Some(url), &flag),
+        );
 
         #[cfg(debug_assertions)]
         {
@@ -159,22 +238,59 @@ Please reply with a simple 'yes' or 'no'.
             }
         }
 
-        Ok(flag)
+        Ok((flag, Some(prompt)))
     }
 
     async fn filter_url_worker(
+        ctx: &Context,
         url: &str,
         comp_name: &str,
         smph: &Semaphore,
     ) -> Result<Option<DLEntry>> {
         let _permit = smph.acquire().await?;
-        if !Self::is_source_link(url, comp_name).await?
-            || !is_url_related_to_comp(url, comp_name).await?
-        {
+        let (is_src, src_prompt) = Self::is_source_link(ctx, url, comp_name).await?;
+        if !is_src {
+            if src_prompt.is_some() {
+                ctx.audit(
+                    comp_name,
+                    AuditEvent::rejected(url, RejectReason::NotSource),
+                );
+            }
             return Ok(None);
         }
+        let (is_related, rel_prompt) = is_url_related_to_comp(ctx, url, comp_name).await?;
+        if !is_related {
+            ctx.audit(
+                comp_name,
+                AuditEvent::rejected(url, RejectReason::NotRelated),
+            );
+            return Ok(None);
+        }
+        let prompts = src_prompt.into_iter().chain([rel_prompt]).collect();
+        Self::entry_from_url(ctx, url, comp_name, prompts)
+    }
 
-        let ent = DLEntry::from_url(url, comp_name)?;
+    /// download entry of an accepted link, none if no version is found in the file name
+    fn entry_from_url(
+        ctx: &Context,
+        url: &str,
+        comp_name: &str,
+        prompts: Vec<PromptRef>,
+    ) -> Result<Option<DLEntry>> {
+        let ver_re = ctx
+            .comp_override(comp_name)
+            .and_then(|ovr| ovr.version_re.as_ref());
+        let mut ent = match ver_re {
+            Some(re) => DLEntry::from_url_with_regex(url, comp_name, re)?,
+            None => DLEntry::from_url(url, comp_name)?,
+        };
+        match ent.as_mut() {
+            Some(ent) => ent.prompts = prompts,
+            None => ctx.audit(
+                comp_name,
+                AuditEvent::rejected(url, RejectReason::NoVersion),
+            ),
+        }
 
         #[cfg(debug_assertions)]
         {
@@ -185,58 +301,263 @@ Please reply with a simple 'yes' or 'no'.
         Ok(ent)
     }
 
-    pub async fn from_page(page_ans: &mut PageAns, comp_name: &str) -> Result<Self> {
-        let page_url = page_ans.get_url();
-        let (dl_pool, abn) = Self::from_page_url(&page_url, comp_name).await?;
-        page_ans.abnoarmal = abn;
-        Ok(dl_pool)
-    }
-
-    pub async fn from_page_url(page_url: &str, comp_name: &str) -> Result<(Self, bool)> {
-        let content = get_page_content(page_url).await?;
-        let url_list = get_all_links(&content, page_url)?;
-        let mut pool = Self {
-            entries: vec![],
-            comp_name: comp_name.to_string(),
-        };
-
+    /// two yes/no llm questions per link
+    async fn filter_links_each(
+        ctx: &Context,
+        link_list: &[PageLink],
+        comp_name: &str,
+    ) -> Result<Vec<DLEntry>> {
         let mut hdl_set = vec![];
-        let smph = Arc::new(construct_semaphore());
+        let smph = Arc::new(construct_semaphore(ctx.api()));
 
-        for url in url_list.iter() {
-            let url = url.clone();
+        for link in link_list.iter() {
+            let url = link.url.clone();
             let comp_name = String::from_str(comp_name)?;
             let smph = smph.clone();
+            let ctx = ctx.clone();
 
-            let hdl =
-                tokio::spawn(async move { Self::filter_url_worker(&url, &comp_name, &smph).await });
+            let hdl = tokio::spawn(async move {
+                Self::filter_url_worker(&ctx, &url, &comp_name, &smph).await
+            });
             hdl_set.push(hdl);
         }
 
+        let mut ents = vec![];
         for hdl in hdl_set {
-            let res = hdl.await?;
-            let ent = res?;
+            if let Some(ent) = hdl.await?? {
+                ents.push(ent);
+            }
+        }
+        Ok(ents)
+    }
 
-            match ent {
-                None => {
-                    continue;
+    /// `--no-llm`: archives named after the component, checked for accessibility
+    async fn filter_links_heuristic(
+        ctx: &Context,
+        mut link_list: Vec<PageLink>,
+        comp_name: &str,
+    ) -> Result<Vec<DLEntry>> {
+        let mut seen = HashSet::new();
+        link_list.retain(|link| seen.insert(link.url.clone()));
+        link_list.retain(|link| {
+            let flag = is_heuristic_source(&link.url, comp_name);
+            if !flag {
+                let detail = "not an archive named after the component";
+                let event = AuditEvent::rejected_for(&link.url, RejectReason::NotSource, detail);
+                ctx.audit(comp_name, event);
+            }
+            flag
+        });
+        log::info!(
+            "{} links of {} look like source archives",
+            link_list.len(),
+            comp_name
+        );
+
+        let smph = Arc::new(construct_semaphore(ctx.api()));
+        let mut hdl_set = vec![];
+        for link in link_list {
+            let comp_name = comp_name.to_string();
+            let smph = smph.clone();
+            let ctx = ctx.clone();
+            let hdl = tokio::spawn(async move {
+                let _permit = smph.acquire().await?;
+                if !Self::is_link_candidate(&ctx, &link.url, &comp_name).await {
+                    return Ok(None);
                 }
-                Some(ent) => {
-                    #[cfg(debug_assertions)]
-                    {
-                        if ent
-                            .url
-                            .eq("https://github.com/opencv/opencv/archive/refs/tags/4.11.0.zip")
-                        {
-                            log::warn!("ent passed in join  for opencv-411zip: {:?}", ent);
+                Self::entry_from_url(&ctx, &link.url, &comp_name, vec![])
+            });
+            hdl_set.push(hdl);
+        }
+        let mut ents = vec![];
+        for hdl in hdl_set {
+            if let Some(ent) = hdl.await?? {
+                ents.push(ent);
+            }
+        }
+        Ok(ents)
+    }
+
+    /// Check links locally, then classify the survivors in chunks of `batch_size` per llm call.
+    async fn filter_links_batched(
+        ctx: &Context,
+        mut link_list: Vec<PageLink>,
+        comp_name: &str,
+        batch_size: usize,
+    ) -> Result<Vec<DLEntry>> {
+        let mut seen = HashSet::new();
+        link_list.retain(|link| seen.insert(link.url.clone()));
+
+        let smph = Arc::new(construct_semaphore(ctx.api()));
+        let mut hdl_set = vec![];
+        for link in link_list {
+            let comp_name = comp_name.to_string();
+            let smph = smph.clone();
+            let ctx = ctx.clone();
+            let hdl = tokio::spawn(async move {
+                let _permit = smph.acquire().await?;
+                let flag = Self::is_link_candidate(&ctx, &link.url, &comp_name).await;
+                Ok::<_, eyre::Report>(flag.then_some(link))
+            });
+            hdl_set.push(hdl);
+        }
+        let mut cand_list = vec![];
+        for hdl in hdl_set {
+            if let Some(link) = hdl.await?? {
+                cand_list.push(link);
+            }
+        }
+        log::info!(
+            "{} candidate links of {} sent for classification",
+            cand_list.len(),
+            comp_name
+        );
+
+        let mut hdl_set = vec![];
+        for chunk in cand_list.chunks(batch_size) {
+            let chunk = chunk.to_vec();
+            let comp_name = comp_name.to_string();
+            let smph = smph.clone();
+            let ctx = ctx.clone();
+            let hdl = tokio::spawn(async move {
+                let _permit = smph.acquire().await?;
+                let (verdicts, prompt) = classify_links(&ctx, &comp_name, &chunk).await?;
+                let mut ents = vec![];
+                for link in chunk.iter() {
+                    let verdict = verdicts.get(&link.url);
+                    if let Some(verdict) = verdict {
+                        let event = AuditEvent::verdict(
+                            Stage::LinkClassification,
+                            Some(&link.url),
+                            verdict,
+                        );
+                        ctx.audit(&comp_name, event);
+                    }
+                    match verdict {
+                        Some(verdict) if verdict.is_accepted() => {
+                            let prompts = vec![prompt.clone()];
+                            if let Some(ent) =
+                                Self::entry_from_url(&ctx, &link.url, &comp_name, prompts)?
+                            {
+                                ents.push(ent);
+                            }
                         }
+                        Some(verdict) => {
+                            log::debug!("link {} rejected: {}", link.url, verdict.reason);
+                            let reason = if verdict.is_source_archive {
+                                RejectReason::NotRelated
+                            } else {
+                                RejectReason::NotSource
+                            };
+                            let event =
+                                AuditEvent::rejected_for(&link.url, reason, &verdict.reason);
+                            ctx.audit(&comp_name, event);
+                        }
+                        None => ctx.audit(
+                            &comp_name,
+                            AuditEvent::rejected(&link.url, RejectReason::Unclassified),
+                        ),
                     }
-                    pool.push_ent(ent);
+                }
+                Ok::<_, eyre::Report>(ents)
+            });
+            hdl_set.push(hdl);
+        }
+        let mut ents = vec![];
+        for hdl in hdl_set {
+            ents.extend(hdl.await??);
+        }
+        Ok(ents)
+    }
+
+    /// Try the candidate pages in rank order until one yields entries, the page is abnormal if none does.
+    pub async fn from_page(ctx: &Context, page_ans: &mut PageAns, comp_name: &str) -> Result<Self> {
+        for (idx, (page_url, content)) in page_ans.take_candidate_pages().into_iter().enumerate() {
+            let dl_pool = match content {
+                Some(content) => Self::from_page_content(ctx, &page_url, &content, comp_name).await,
+                None => Self::from_page_url(ctx, &page_url, comp_name).await,
+            };
+            let dl_pool = match dl_pool {
+                Ok((dl_pool, _)) => dl_pool,
+                Err(e) => {
+                    log::warn!("candidate page {} of {} failed: {}", page_url, comp_name, e);
+                    page_ans.record_attempt(idx, 0);
+                    continue;
+                }
+            };
+            page_ans.record_attempt(idx, dl_pool.len());
+            if !dl_pool.is_empty() {
+                // entries of an unverified page are kept for review
+                page_ans.abnoarmal |= page_ans.is_unverified();
+                return Ok(dl_pool);
+            }
+            log::warn!("no entries on candidate page {} of {}", page_url, comp_name);
+        }
+        page_ans.abnoarmal = true;
+        Ok(Self {
+            comp_name: comp_name.to_string(),
+            entries: vec![],
+        })
+    }
+
+    pub async fn from_page_url(
+        ctx: &Context,
+        page_url: &str,
+        comp_name: &str,
+    ) -> Result<(Self, bool)> {
+        let content = get_page_content(ctx, page_url).await?;
+        Self::from_page_content(ctx, page_url, &content, comp_name).await
+    }
+
+    /// entries from the already fetched content of the page at `page_url`
+    async fn from_page_content(
+        ctx: &Context,
+        page_url: &str,
+        content: &str,
+        comp_name: &str,
+    ) -> Result<(Self, bool)> {
+        let mut link_list = get_all_links(content, page_url)?;
+        if let Some(ovr) = ctx.comp_override(comp_name) {
+            link_list.retain(|link| {
+                let flag = ovr.is_url_allowed(&link.url);
+                if !flag {
+                    ctx.audit(
+                        comp_name,
+                        AuditEvent::rejected(&link.url, RejectReason::Excluded),
+                    );
+                }
+                flag
+            });
+            log::info!(
+                "{} links left for {} after override globs",
+                link_list.len(),
+                comp_name
+            );
+        }
+        let mut pool = Self {
+            entries: vec![],
+            comp_name: comp_name.to_string(),
+        };
+
+        let ents = match ctx.api().get_link_batch_size() {
+            _ if ctx.no_llm() => Self::filter_links_heuristic(ctx, link_list, comp_name).await?,
+            0 => Self::filter_links_each(ctx, &link_list, comp_name).await?,
+            batch_size => Self::filter_links_batched(ctx, link_list, comp_name, batch_size).await?,
+        };
+        for ent in ents {
+            #[cfg(debug_assertions)]
+            {
+                if ent
+                    .url
+                    .eq("https://github.com/opencv/opencv/archive/refs/tags/4.11.0.zip")
+                {
+                    log::warn!("ent passed in join  for opencv-411zip: {:?}", ent);
                 }
             }
+            pool.push_ent(ent);
         }
 
-        let cnt = get_ver_cnt();
+        let cnt = ctx.get_comp_ver_cnt(comp_name);
         pool.entries.sort_by(|a, b| b.cmp(a));
 
         let abn = pool.is_empty();
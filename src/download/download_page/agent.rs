@@ -0,0 +1,362 @@
+//! Discovery by a model that browses before it answers.
+//!
+//! The model replies with one json step per turn, either a tool call or the final answer.
+//! Tool results go back as the next user message, up to `agent.max_tool_calls` calls.
+//!
+//! Tool calling is emulated with these json steps instead of the native tool apis of the providers:
+//! openai, anthropic and ollama each encode tool calls differently and many openai compatible
+//! servers do not implement them, while a json step is a plain structured answer that goes through
+//! the llm cache, the cassette, the usage stats and the audit log like every other call.
+
+use std::net::IpAddr;
+
+use color_eyre::eyre::Result;
+use reqwest::{Client, Response, Url, redirect};
+use schemars::JsonSchema;
+use scraper::{Html, Node};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::{entities::PageAns, get_prompt_for_comp};
+use crate::{
+    context::Context,
+    download::download_link::{fetch_page_content, get_all_links},
+    llm_api::{
+        entities::LLMMsg,
+        get_llm_structured_chat,
+        prompts::PromptRef,
+        usage::{CallMeta, Stage},
+    },
+    utils::MAX_REDIRECTS,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentTool {
+    /// visible text of a page
+    FetchUrl,
+    /// links of a page along with their anchor text
+    ListLinks,
+}
+
+/// one turn of the model, a tool call or the answer
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+pub struct AgentStep {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool: Option<AgentTool>,
+    /// page the tool is called on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    /// final answer, once a page listing source releases is confirmed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    answer: Option<PageAns>,
+}
+
+/// a tool call made before answering, kept on [`PageAns::tool_calls`]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ToolCall {
+    pub tool: AgentTool,
+    pub url: String,
+    /// whether the page could be fetched
+    pub ok: bool,
+}
+
+/// text of the page without scripts and styles, whitespace collapsed
+fn page_text(page_content: &str) -> String {
+    let doc = Html::parse_document(page_content);
+    let mut words = vec![];
+    for node in doc.root_element().descendants() {
+        let Node::Text(text) = node.value() else {
+            continue;
+        };
+        let hidden = node
+            .parent()
+            .and_then(|par| par.value().as_element().map(|ele| ele.name()))
+            .is_some_and(|name| matches!(name, "script" | "style" | "noscript"));
+        if !hidden {
+            words.extend(text.split_whitespace());
+        }
+    }
+    words.join(" ")
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((idx, _)) => format!("{}\n[truncated]", &text[..idx]),
+        None => text.to_string(),
+    }
+}
+
+/// loopback, private, link-local and other addresses not reachable on the internet
+fn is_private_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_private_ip(IpAddr::V4(ip)),
+            None => {
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local()
+            }
+        },
+    }
+}
+
+/// Why the tools refuse `url`: only http(s) urls on a public host are opened,
+/// unless `agent.allow_private_hosts` lets private ones through.
+fn refuse_url(url: &str, allow_private: bool) -> Option<String> {
+    let parsed = match Url::parse(url) {
+        Ok(parsed) => parsed,
+        Err(e) => return Some(format!("invalid url: {e}")),
+    };
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Some(format!("{} urls are not opened", parsed.scheme()));
+    }
+    let Some(host) = parsed.host_str() else {
+        return Some("url has no host".to_string());
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let private = match host.parse::<IpAddr>() {
+        Ok(ip) => is_private_ip(ip),
+        Err(_) => {
+            let host = host.trim_end_matches('.').to_lowercase();
+            host == "localhost" || host.ends_with(".localhost") || host.ends_with(".local")
+        }
+    };
+    (private && !allow_private).then(|| "host is not public".to_string())
+}
+
+/// client of the tools, a redirect to a url the tools refuse is not followed
+fn tool_client(allow_private: bool) -> Result<Client> {
+    let policy = redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() > MAX_REDIRECTS {
+            return attempt.error("too many redirects");
+        }
+        match refuse_url(attempt.url().as_str(), allow_private) {
+            Some(reason) => {
+                let error = format!("redirect to {} refused: {}", attempt.url(), reason);
+                attempt.error(error)
+            }
+            None => attempt.follow(),
+        }
+    });
+    Ok(Client::builder().redirect(policy).build()?)
+}
+
+/// Why the tools drop an answer: its final url is refused, or the host resolved to
+/// an address that is not public.
+fn refuse_resp(resp: &Response, allow_private: bool) -> Option<String> {
+    if let Some(reason) = refuse_url(resp.url().as_str(), allow_private) {
+        return Some(reason);
+    }
+    let addr = resp.remote_addr()?;
+    (is_private_ip(addr.ip()) && !allow_private)
+        .then(|| format!("host resolved to {} which is not public", addr.ip()))
+}
+
+/// output of a tool as handed back to the model, with whether it succeeded
+async fn run_tool(ctx: &Context, cli: &Client, tool: AgentTool, url: &str) -> (String, bool) {
+    let agent = ctx.agent();
+    let max_chars = agent.get_max_page_chars();
+    let allow_private = agent.get_allow_private_hosts();
+    let refuse = |resp: &Response| refuse_resp(resp, allow_private);
+    let content = match fetch_page_content(ctx, url, cli, refuse).await {
+        Ok(content) => content,
+        Err(e) => return (format!("{tool:?} failed on {url}: {e}"), false),
+    };
+    match tool {
+        AgentTool::FetchUrl => {
+            let text = truncate(&page_text(&content), max_chars);
+            (format!("Text of {url}:\n{text}"), true)
+        }
+        AgentTool::ListLinks => match get_all_links(&content, url) {
+            Ok(links) => {
+                let lines: Vec<String> = links
+                    .iter()
+                    .map(|link| format!("{} {}", link.url, link.text))
+                    .collect();
+                let text = truncate(&lines.join("\n"), max_chars);
+                (format!("Links on {url}:\n{text}"), true)
+            }
+            Err(e) => (format!("{tool:?} failed on {url}: {e}"), false),
+        },
+    }
+}
+
+/// Let the model browse with tools until it answers or runs out of tool calls.
+/// A model still calling tools after the last one gets an unavailable answer.
+pub async fn agent_download_page(
+    ctx: &Context,
+    comp_name: &str,
+) -> Result<(PageAns, Vec<PromptRef>)> {
+    let agent = ctx.agent();
+    let max_calls = agent.get_max_tool_calls();
+    let data = json!({ "max_tool_calls": max_calls });
+    let (system, sys_prompt) = ctx.prompts().render("agent_system", &data)?;
+    let (query, prompt) = get_prompt_for_comp(ctx, comp_name)?;
+    let mut msgs = vec![LLMMsg::system(&system), LLMMsg::user(&query)];
+    let prompts = vec![sys_prompt, prompt];
+    let meta = CallMeta::new(comp_name, Stage::Discovery);
+    let cli = tool_client(agent.get_allow_private_hosts())?;
+
+    let mut calls: Vec<ToolCall> = vec![];
+    // tool calls that opened a page, refused and malformed steps do not count
+    let mut used = 0;
+    loop {
+        let (step, _) = get_llm_structured_chat::<AgentStep>(ctx, &meta, msgs.clone()).await?;
+        if let Some(mut ans) = step.answer {
+            log::info!(
+                "agent answered for {} after {} tool calls",
+                comp_name,
+                calls.len()
+            );
+            ans.tool_calls = calls;
+            return Ok((ans, prompts));
+        }
+        // every turn adds to `calls`, so the loop ends even if the model never calls a tool right
+        if used >= max_calls || calls.len() >= 2 * max_calls {
+            log::warn!("agent ran out of tool calls for {}", comp_name);
+            let mut ans = PageAns::from_candidates(comp_name, vec![]);
+            ans.tool_calls = calls;
+            return Ok((ans, prompts));
+        }
+
+        let mut observation = match (step.tool, step.url.as_deref()) {
+            (Some(tool), Some(url)) => match refuse_url(url, agent.get_allow_private_hosts()) {
+                Some(reason) => {
+                    log::warn!("agent of {} refused to open {}: {}", comp_name, url, reason);
+                    calls.push(ToolCall {
+                        tool,
+                        url: url.to_string(),
+                        ok: false,
+                    });
+                    format!(
+                        "{tool:?} refused on {url}: {reason}. This does not use up a tool call."
+                    )
+                }
+                None => {
+                    log::debug!("agent of {} calls {:?} on {}", comp_name, tool, url);
+                    let (text, ok) = run_tool(ctx, &cli, tool, url).await;
+                    used += 1;
+                    calls.push(ToolCall {
+                        tool,
+                        url: url.to_string(),
+                        ok,
+                    });
+                    text
+                }
+            },
+            _ => {
+                calls.push(ToolCall {
+                    tool: step.tool.unwrap_or(AgentTool::FetchUrl),
+                    url: step.url.clone().unwrap_or_default(),
+                    ok: false,
+                });
+                "Either call a tool with a `url` or give the `answer`.".to_string()
+            }
+        };
+        if used >= max_calls {
+            observation.push_str("\n\nNo tool calls left, reply with the answer now.");
+        }
+        msgs.push(LLMMsg::assistant(&serde_json::to_string(&step)?));
+        msgs.push(LLMMsg::user(&observation));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mock_llm::{MockPage, MockRule, MockScript, MockServer},
+        test_util::mock_ctx,
+    };
+
+    #[test]
+    fn test_refuse_url() {
+        assert_eq!(refuse_url("https://ftp.gnu.org/gnu/wget/", false), None);
+        assert!(refuse_url("file:///etc/passwd", false).is_some());
+        assert!(refuse_url("http://localhost:8080/", false).is_some());
+        assert!(refuse_url("http://10.1.2.3/", false).is_some());
+        assert!(refuse_url("http://169.254.169.254/latest/", false).is_some());
+        assert!(refuse_url("http://[::1]/", false).is_some());
+        assert!(refuse_url("http://[::ffff:192.168.0.1]/", false).is_some());
+        assert_eq!(refuse_url("http://127.0.0.1:8089/foo/", true), None);
+        assert!(refuse_url("not a url", true).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_tools_refuse_private_redirects() -> Result<()> {
+        let script = MockScript::default()
+            .page(MockPage::redirect("/old/", "{{base_url}}/new/"))
+            .page(MockPage::html("/new/", "new"));
+        let server = MockServer::start(script).await?;
+        let url = format!("{}/old/", server.base_url());
+
+        // the requested url is checked before the tool runs, the redirect by the client
+        let err = tool_client(false)?.get(&url).send().await.unwrap_err();
+        assert!(err.is_redirect(), "{err:?}");
+        let resp = tool_client(true)?.get(&url).send().await?;
+        assert_eq!(resp.url().path(), "/new/");
+        assert!(refuse_resp(&resp, false).is_some());
+        assert_eq!(refuse_resp(&resp, true), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_page_text() {
+        let html = "<html><head><title>Index of /foo</title><style>a {}</style></head>\
+            <body><script>var x;</script><h1>Index   of /foo</h1><a href=\"foo-1.0.tar.gz\">foo-1.0.tar.gz</a></body></html>";
+        assert_eq!(
+            page_text(html),
+            "Index of /foo Index of /foo foo-1.0.tar.gz"
+        );
+        assert_eq!(truncate("abcdef", 3), "abc\n[truncated]");
+    }
+
+    #[tokio::test]
+    async fn test_agent_with_mock() -> Result<()> {
+        let script = MockScript::default()
+            .rule(MockRule::reply(
+                "official download URL for source code of the open-source component foo",
+                r#"{"tool": "list_links", "url": "{{base_url}}/foo/"}"#,
+            ))
+            .rule(MockRule::reply(
+                "(?s)^Links on .*foo-1.0.tar.gz",
+                r#"{"tool": "fetch_url", "url": "{{base_url}}/foo/"}"#,
+            ))
+            .rule(MockRule::reply(
+                "(?s)No tool calls left",
+                r#"{"answer": {"component_name": "foo", "available": true, "site_url": "{{base_url}}/foo/"}}"#,
+            ))
+            .page(MockPage::html(
+                "/foo/",
+                r#"<a href="foo-1.0.tar.gz">foo-1.0.tar.gz</a>"#,
+            ));
+        let server = MockServer::start(script).await?;
+        let ctx = mock_ctx(
+            &server,
+            "/nonexistent",
+            "\n[agent]\nenabled = true\nmax_tool_calls = 2\nallow_private_hosts = true",
+        )?;
+
+        let (ans, prompts) = agent_download_page(&ctx, "foo").await?;
+        assert_eq!(
+            ans.site_url(),
+            Some(format!("{}/foo/", server.base_url()).as_str())
+        );
+        let tools: Vec<AgentTool> = ans.tool_calls.iter().map(|call| call.tool).collect();
+        assert_eq!(tools, [AgentTool::ListLinks, AgentTool::FetchUrl]);
+        assert!(ans.tool_calls.iter().all(|call| call.ok));
+        assert_eq!(server.prompts().len(), 3);
+        assert_eq!(prompts[0].name, "agent_system");
+        Ok(())
+    }
+}
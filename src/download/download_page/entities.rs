@@ -1,58 +1,381 @@
 use color_eyre::eyre::Result;
-use regex::Regex;
-use reqwest::Url;
+use futures::future::join_all;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::utils::is_url_accessible;
+use super::{
+    agent::ToolCall,
+    verify::{PageVerdict, verify_page},
+    vote::normalize_url,
+};
+use crate::{
+    audit::{AuditEvent, RejectReason},
+    context::Context,
+    llm_api::{prompts::PromptRef, search::Citation},
+    utils::{UrlAccess, is_url_accessible},
+};
 
+/// where a candidate download page comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CandidateSource {
+    /// `site_url` of the answer
+    Answer,
+    /// `alt_urls` of the answer
+    Alternative,
+    /// search result cited by a search model
+    Citation,
+    /// pinned by a component override
+    Override,
+    /// listed in `heuristic.page_list`
+    List,
+    /// well-known source host of the registry
+    Registry,
+}
+
+/// a possible download page, ranked by its position in [`PageAns::candidates`]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PageCandidate {
+    pub url: String,
+    pub source: CandidateSource,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// entries the page yielded, none if it was not tried
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entries: Option<usize>,
+    /// content verification, none if the page was not verified
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verdict: Option<PageVerdict>,
+    /// accessibility check, none if the page was not checked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access: Option<UrlAccess>,
+    /// html fetched for verification, reused for link extraction
+    #[serde(skip)]
+    pub content: Option<String>,
+}
+
+impl PageCandidate {
+    pub fn new(url: &str, source: CandidateSource) -> Self {
+        Self {
+            url: url.to_string(),
+            source,
+            title: None,
+            entries: None,
+            verdict: None,
+            access: None,
+            content: None,
+        }
+    }
+
+    fn is_verified(&self) -> bool {
+        self.verdict.as_ref().is_some_and(|it| it.verified)
+    }
+}
+
+/// candidate page dropped before any link was extracted
 #[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RejectedPage {
+    pub url: String,
+    pub reason: RejectReason,
+    /// status of the failed check or why the official-source policy turned the page down
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// one answer of self-consistency voting
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PageVote {
+    pub model: String,
+    /// none for an answer without a download page
+    pub site_url: Option<String>,
+}
+
+/// answer to the download page discovery prompt
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct PageAns {
+    /// full name of the component
     component_name: String,
+    /// whether an official download site is accessible
     available: bool,
+    /// url of the official download site
     site_url: Option<String>,
+    /// further download pages of the component, best first, e.g. the ftp/dist directory or a mirror
+    #[serde(default)]
+    alt_urls: Vec<String>,
     #[serde(skip_deserializing, default)]
     pub abnoarmal: bool,
+    /// ranked download pages, the answer first; only accessible official ones once resolved
+    #[serde(skip_deserializing, default)]
+    #[schemars(skip)]
+    pub candidates: Vec<PageCandidate>,
+    /// discovery prompt templates, empty for pinned pages
+    #[serde(skip_deserializing, default)]
+    #[schemars(skip)]
+    pub prompts: Vec<PromptRef>,
+    /// share of votes for the chosen answer, none without voting
+    #[serde(skip_deserializing, default)]
+    #[schemars(skip)]
+    pub agreement: Option<f64>,
+    #[serde(skip_deserializing, default)]
+    #[schemars(skip)]
+    pub votes: Vec<PageVote>,
+    /// pages the agent looked at before answering, empty outside `[agent]` mode
+    #[serde(skip_deserializing, default, skip_serializing_if = "Vec::is_empty")]
+    #[schemars(skip)]
+    pub tool_calls: Vec<ToolCall>,
+    /// candidates that were inaccessible or not official
+    #[serde(skip_deserializing, default, skip_serializing_if = "Vec::is_empty")]
+    #[schemars(skip)]
+    pub rejected: Vec<RejectedPage>,
+    /// content verification of `site_url`, none with `verify.enabled = false` or for pinned pages
+    #[serde(skip_deserializing, default)]
+    #[schemars(skip)]
+    pub verification: Option<PageVerdict>,
 }
 
 impl PageAns {
-    pub async fn refrac_with_valid_url(&self) -> Result<Option<PageAns>> {
-        if !self.available || self.site_url.is_none() {
-            log::warn!("component {} is not available", self.component_name);
-            return Ok(None);
+    /// answer for a download page pinned by a component override
+    pub fn pinned(comp_name: &str, url: &str) -> Self {
+        Self {
+            component_name: comp_name.to_string(),
+            available: true,
+            site_url: Some(url.to_string()),
+            alt_urls: vec![],
+            abnoarmal: false,
+            candidates: vec![PageCandidate::new(url, CandidateSource::Override)],
+            prompts: vec![],
+            agreement: None,
+            votes: vec![],
+            tool_calls: vec![],
+            rejected: vec![],
+            verification: None,
         }
-        if self.site_url.is_none() {
-            return Ok(None);
+    }
+
+    /// answer for an accessible page of `heuristic.page_list`, trusted like a pinned one
+    pub fn listed(comp_name: &str, url: &str, access: UrlAccess) -> Self {
+        Self {
+            candidates: vec![PageCandidate {
+                access: Some(access),
+                ..PageCandidate::new(url, CandidateSource::List)
+            }],
+            ..Self::pinned(comp_name, url)
         }
+    }
 
-        let url = self.site_url.as_ref().unwrap().clone();
-        let (flag, url_op) = is_url_accessible(&url).await;
-        if !flag {
-            log::warn!(
-                "url {} of component {} is not accessible",
-                url,
-                self.component_name
-            );
-            return Ok(None);
+    /// unanswered discovery over ranked candidates, resolved by [`PageAns::refrac_with_valid_url`]
+    pub fn from_candidates(comp_name: &str, candidates: Vec<PageCandidate>) -> Self {
+        Self {
+            component_name: comp_name.to_string(),
+            available: !candidates.is_empty(),
+            site_url: candidates.first().map(|cand| cand.url.clone()),
+            alt_urls: vec![],
+            abnoarmal: false,
+            candidates,
+            prompts: vec![],
+            agreement: None,
+            votes: vec![],
+            tool_calls: vec![],
+            rejected: vec![],
+            verification: None,
         }
+    }
 
-        let res_url = url_op.unwrap();
+    /// what the answer votes for, equal for urls naming the same page
+    pub fn vote_key(&self) -> Option<String> {
+        if !self.available {
+            return None;
+        }
+        self.site_url.as_deref().map(normalize_url)
+    }
+
+    /// Rank the answer's `site_url` first, then its `alt_urls`, then cited pages mentioning
+    /// the requested component, not the name the model echoed.
+    pub fn with_citations(mut self, comp_name: &str, citations: &[Citation]) -> Self {
+        let mut cands: Vec<PageCandidate> = vec![];
+        if let Some(url) = self.site_url.as_ref() {
+            cands.push(PageCandidate::new(url, CandidateSource::Answer));
+        }
+        for url in self.alt_urls.iter() {
+            if cands.iter().any(|it| it.url == *url) {
+                continue;
+            }
+            cands.push(PageCandidate::new(url, CandidateSource::Alternative));
+        }
+        let name = comp_name.to_lowercase();
+        for cite in citations.iter() {
+            let mentioned = cite.url.to_lowercase().contains(&name)
+                || cite.title.to_lowercase().contains(&name);
+            if !mentioned || cands.iter().any(|it| it.url == cite.url) {
+                continue;
+            }
+            cands.push(PageCandidate {
+                title: Some(cite.title.clone()),
+                ..PageCandidate::new(&cite.url, CandidateSource::Citation)
+            });
+        }
+        self.candidates = cands;
+        self
+    }
 
-        if !Self::is_official_url(&res_url)? {
+    /// Keep the accessible official candidates under their final urls, the first becomes `site_url`.
+    /// Candidates are checked concurrently. If all of them are rejected, the answer is kept as
+    /// unavailable and abnormal along with the rejections and its vote.
+    pub async fn refrac_with_valid_url(&self, ctx: &Context) -> Result<Option<PageAns>> {
+        if !self.available || self.candidates.is_empty() {
+            log::warn!("component {} is not available", self.component_name);
+            // a low-agreement vote is kept for review
+            return Ok(self.abnoarmal.then(|| self.clone()));
+        }
+
+        let checks = self
+            .candidates
+            .iter()
+            .map(|cand| is_url_accessible(ctx, &cand.url));
+        let checks = join_all(checks).await;
+
+        let mut valid: Vec<PageCandidate> = vec![];
+        let mut rejected: Vec<RejectedPage> = vec![];
+        for (cand, access) in self.candidates.iter().zip(checks) {
+            let url = &cand.url;
+            let res_url = match access.final_url.as_ref() {
+                Some(res_url) if access.is_accessible() => res_url.clone(),
+                _ => {
+                    let detail = access.describe();
+                    log::warn!(
+                        "url {} of component {} is not accessible: {}",
+                        url,
+                        self.component_name,
+                        detail
+                    );
+                    ctx.audit(
+                        &self.component_name,
+                        AuditEvent::rejected_for(url, RejectReason::Inaccessible, &detail),
+                    );
+                    rejected.push(RejectedPage {
+                        url: url.clone(),
+                        reason: RejectReason::Inaccessible,
+                        detail: Some(detail),
+                    });
+                    continue;
+                }
+            };
+
+            let verdict = ctx.judge_url(&self.component_name, &res_url);
+            if !verdict.official {
+                log::warn!(
+                    "url {url} of component {} is not url of official site: {}",
+                    self.component_name,
+                    verdict.reason
+                );
+                ctx.audit(
+                    &self.component_name,
+                    AuditEvent::rejected_for(&res_url, RejectReason::NotOfficial, &verdict.reason),
+                );
+                rejected.push(RejectedPage {
+                    url: res_url,
+                    reason: RejectReason::NotOfficial,
+                    detail: Some(verdict.reason),
+                });
+                continue;
+            }
+            log::info!(
+                "url for component {} if {} ({:?})",
+                self.component_name,
+                res_url,
+                cand.source
+            );
+            if !valid.iter().any(|it| it.url == res_url) {
+                valid.push(PageCandidate {
+                    url: res_url,
+                    access: Some(access),
+                    ..cand.clone()
+                });
+            }
+        }
+        if ctx.verify().enabled {
+            let verdicts = valid
+                .iter()
+                .map(|cand| verify_page(ctx, &cand.url, &self.component_name));
+            let verdicts = join_all(verdicts).await;
+            for (cand, (verdict, content)) in valid.iter_mut().zip(verdicts) {
+                if !verdict.verified {
+                    log::warn!(
+                        "page {} of component {} is not verified: {:?}",
+                        cand.url,
+                        self.component_name,
+                        verdict.issues
+                    );
+                }
+                cand.verdict = Some(verdict);
+                cand.content = content;
+            }
+            // verified pages first, the rank is kept otherwise
+            valid.sort_by_key(|cand| !cand.is_verified());
+        }
+        let Some(first) = valid.first() else {
             log::warn!(
-                "url {url} of component {} is not url of official site",
+                "all {} candidate pages of {} are rejected",
+                rejected.len(),
                 self.component_name
             );
-            return Ok(None);
-        }
-        log::info!("url for component {} if {}", self.component_name, res_url);
+            return Ok(Some(Self {
+                available: false,
+                site_url: None,
+                abnoarmal: true,
+                candidates: vec![],
+                rejected,
+                ..self.clone()
+            }));
+        };
         Ok(Some(Self {
-            component_name: self.component_name.clone(),
             available: true,
-            site_url: Some(res_url),
-            abnoarmal: false,
+            site_url: Some(first.url.clone()),
+            verification: first.verdict.clone(),
+            candidates: valid,
+            rejected,
+            ..self.clone()
         }))
     }
 
+    /// Urls of the ranked candidates along with the content fetched for verification,
+    /// `site_url` alone if there are none. The content is taken out of the candidates.
+    pub fn take_candidate_pages(&mut self) -> Vec<(String, Option<String>)> {
+        if self.candidates.is_empty() {
+            return vec![(self.get_url(), None)];
+        }
+        self.candidates
+            .iter_mut()
+            .map(|cand| (cand.url.clone(), cand.content.take()))
+            .collect()
+    }
+
+    /// Record the entries the `idx`-th candidate yielded, a page with entries becomes `site_url`.
+    pub fn record_attempt(&mut self, idx: usize, entries: usize) {
+        let Some(cand) = self.candidates.get_mut(idx) else {
+            return;
+        };
+        cand.entries = Some(entries);
+        if entries > 0 {
+            self.site_url = Some(cand.url.clone());
+            self.verification = cand.verdict.clone();
+        }
+    }
+
+    /// whether `site_url` failed content verification, an unverified page is not
+    pub fn is_unverified(&self) -> bool {
+        self.verification.as_ref().is_some_and(|it| !it.verified)
+    }
+
+    /// whether an official download page is known, false for an answer whose candidates were all rejected
+    pub fn is_available(&self) -> bool {
+        self.available
+    }
+
+    pub fn site_url(&self) -> Option<&str> {
+        self.site_url.as_deref()
+    }
+
     pub fn get_url(&self) -> String {
         self.site_url
             .as_ref()
@@ -61,40 +384,64 @@ impl PageAns {
             })
             .clone()
     }
-
-    fn is_official_url(url: &str) -> Result<bool> {
-        let url_par = Url::parse(url)?;
-        let host_str = url_par.host_str();
-        match host_str {
-            None => {
-                log::warn!("Failed to get host part of the url: {:?}", url);
-                Ok(false)
-            }
-            Some(val) => {
-                let re = Regex::new(r"gitlab\.(\w+\.)?com")?;
-                if val.contains("github.com") || re.is_match(val) {
-                    Ok(false)
-                } else {
-                    Ok(true)
-                }
-            }
-        }
-    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::utils::init_report_utils;
-
     use super::*;
-    use color_eyre::eyre::Result;
 
     #[test]
-    fn test_gitlab_regex() -> Result<()> {
-        init_report_utils()?;
-        let re = Regex::new(r"gitlab\.(\w+\.)?com")?;
-        let hay = "gitlab.unitslink.com";
-        assert!(re.is_match(hay));
-        Ok(())
+    fn test_page_ans_schema() {
+        let schema = schemars::schema_for!(PageAns).to_value();
+        let props = schema["properties"].as_object().unwrap();
+        assert!(props.contains_key("site_url"));
+        assert!(!props.contains_key("abnoarmal"));
+        assert!(!props.contains_key("candidates"));
+    }
+
+    #[test]
+    fn test_citation_candidates() {
+        let cite = |url: &str, title: &str| Citation {
+            url: url.to_string(),
+            title: title.to_string(),
+            site_name: String::new(),
+            rel_score: None,
+            auth_score: None,
+            cited: true,
+        };
+        let ans = PageAns {
+            // echoed by the model, the citations are matched on the requested name
+            component_name: "GNU Wget2".to_string(),
+            available: true,
+            site_url: Some("https://ftp.gnu.org/gnu/wget/".to_string()),
+            alt_urls: vec!["https://ftpmirror.gnu.org/wget/".to_string()],
+            abnoarmal: false,
+            candidates: vec![],
+            prompts: vec![],
+            agreement: None,
+            votes: vec![],
+            tool_calls: vec![],
+            rejected: vec![],
+            verification: None,
+        }
+        .with_citations(
+            "wget",
+            &[
+                cite("https://ftp.gnu.org/gnu/wget/", "Index of /gnu/wget"),
+                cite("https://m.58.com", "classifieds"),
+                cite("https://www.gnu.org/software/", "GNU Wget"),
+            ],
+        );
+        let urls: Vec<&str> = ans.candidates.iter().map(|it| it.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            [
+                "https://ftp.gnu.org/gnu/wget/",
+                "https://ftpmirror.gnu.org/wget/",
+                "https://www.gnu.org/software/"
+            ]
+        );
+        assert_eq!(ans.candidates[1].source, CandidateSource::Alternative);
+        assert_eq!(ans.candidates[2].source, CandidateSource::Citation);
     }
 }
@@ -0,0 +1,84 @@
+use std::{fs, path::Path};
+
+use color_eyre::eyre::Result;
+use serde::Deserialize;
+use serde_json::json;
+
+/// a download page confirmed by an earlier run, read back from `available_url_list.json`
+#[derive(Debug, Clone, Deserialize)]
+pub struct VerifiedPage {
+    pub component_name: String,
+    available: bool,
+    pub site_url: Option<String>,
+    #[serde(default)]
+    abnoarmal: bool,
+}
+
+impl VerifiedPage {
+    /// the answer the model should have given, as a json object
+    pub fn answer_json(&self) -> String {
+        json!({
+            "component_name": self.component_name,
+            "available": true,
+            "site_url": self.site_url,
+        })
+        .to_string()
+    }
+}
+
+/// Available pages that yielded download links, sorted by component name.
+/// A missing file means no examples yet.
+pub fn load_verified_pages(fpath: &Path) -> Result<Vec<VerifiedPage>> {
+    if !fpath.is_file() {
+        return Ok(vec![]);
+    }
+    let pages: Vec<VerifiedPage> = serde_json::from_str(&fs::read_to_string(fpath)?)?;
+    let mut pages: Vec<VerifiedPage> = pages
+        .into_iter()
+        .filter(|page| page.available && !page.abnoarmal && page.site_url.is_some())
+        .collect();
+    pages.sort_by(|a, b| a.component_name.cmp(&b.component_name));
+    pages.dedup_by(|a, b| a.component_name == b.component_name);
+    Ok(pages)
+}
+
+/// first `cnt` verified pages of other components, stable across runs to keep answers cacheable
+pub fn pick_examples<'a>(
+    pages: &'a [VerifiedPage],
+    comp_name: &str,
+    cnt: usize,
+) -> Vec<&'a VerifiedPage> {
+    pages
+        .iter()
+        .filter(|page| !page.component_name.eq_ignore_ascii_case(comp_name))
+        .take(cnt)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pick_examples() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let fpath = tmp.path().join("verified.json");
+        fs::write(
+            &fpath,
+            r#"[
+    {"component_name": "wget", "available": true, "site_url": "https://ftp.gnu.org/gnu/wget/", "abnoarmal": false},
+    {"component_name": "bash", "available": true, "site_url": "https://ftp.gnu.org/gnu/bash/", "abnoarmal": false},
+    {"component_name": "iftop", "available": true, "site_url": "https://www.ex-parrot.com/pdw/iftop/", "abnoarmal": true}
+]"#,
+        )?;
+        let pages = load_verified_pages(&fpath)?;
+        let names: Vec<&str> = pick_examples(&pages, "Bash", 3)
+            .iter()
+            .map(|page| page.component_name.as_str())
+            .collect();
+        assert_eq!(names, ["wget"]);
+        assert!(pages[0].answer_json().contains("ftp.gnu.org/gnu/bash/"));
+        assert!(load_verified_pages(Path::new("/nonexistent/list.json"))?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use color_eyre::eyre::Result;
+use eyre::WrapErr;
+
+use super::{
+    entities::{PageAns, RejectedPage},
+    registry::registry_candidates,
+};
+use crate::{
+    audit::{AuditEvent, RejectReason},
+    context::Context,
+    utils::is_url_accessible,
+};
+
+/// `component = "url"` pairs of `heuristic.page_list`, a missing file means an empty list
+pub fn load_page_list(fpath: &Path) -> Result<HashMap<String, String>> {
+    if !fpath.is_file() {
+        return Ok(HashMap::new());
+    }
+    let toml_str = fs::read_to_string(fpath)?;
+    let pages: HashMap<String, String> =
+        toml::from_str(&toml_str).wrap_err_with(|| format!("invalid page list {fpath:?}"))?;
+    log::info!("{} download pages loaded from {:?}", pages.len(), fpath);
+    Ok(pages)
+}
+
+/// Download pages without the llm. An accessible listed page is taken like a pinned one,
+/// skipping the policy and verification, otherwise the registry pages are checked.
+pub async fn discover_page(ctx: &Context, comp_name: &str) -> Result<Option<PageAns>> {
+    let mut listed_rejected = None;
+    if let Some(url) = ctx.listed_page(comp_name) {
+        let access = is_url_accessible(ctx, url).await;
+        match access.final_url.clone() {
+            Some(res_url) if access.is_accessible() => {
+                log::info!("download page of {} is listed as {}", comp_name, res_url);
+                return Ok(Some(PageAns::listed(comp_name, &res_url, access)));
+            }
+            _ => {
+                let detail = access.describe();
+                log::warn!(
+                    "listed page {} of component {} is not accessible: {}",
+                    url,
+                    comp_name,
+                    detail
+                );
+                ctx.audit(
+                    comp_name,
+                    AuditEvent::rejected_for(url, RejectReason::Inaccessible, &detail),
+                );
+                listed_rejected = Some(RejectedPage {
+                    url: url.to_string(),
+                    reason: RejectReason::Inaccessible,
+                    detail: Some(detail),
+                });
+            }
+        }
+    }
+    let cands = if ctx.registry().enabled {
+        registry_candidates(ctx, comp_name)?
+    } else {
+        vec![]
+    };
+    log::info!(
+        "probe {} candidate pages of {} without llm",
+        cands.len(),
+        comp_name
+    );
+    let mut ans = PageAns::from_candidates(comp_name, cands)
+        .refrac_with_valid_url(ctx)
+        .await?;
+    // an inaccessible listed page is kept for review
+    if let Some(page) = listed_rejected {
+        let ans = ans.get_or_insert_with(|| {
+            let mut ans = PageAns::from_candidates(comp_name, vec![]);
+            ans.abnoarmal = true;
+            ans
+        });
+        ans.rejected.insert(0, page);
+    }
+    Ok(ans)
+}
@@ -1,83 +1,129 @@
 use std::{str::FromStr, sync::Arc};
 
 use color_eyre::eyre::Result;
-use sanitize::sanitize_ans;
 use serde_json::json;
 use tokio::sync::Semaphore;
 
-use crate::{llm_api::get_llm_completion, utils::construct_semaphore};
+use crate::{
+    audit::AuditEvent,
+    context::Context,
+    llm_api::{
+        entities::LLMMsg,
+        get_llm_structured_chat,
+        prompts::PromptRef,
+        usage::{CallMeta, Stage},
+    },
+    utils::construct_semaphore,
+};
+use agent::agent_download_page;
 use entities::PageAns;
+use few_shot::pick_examples;
+use heuristic::discover_page;
+use registry::registry_page;
+use vote::vote_download_page;
 
+pub mod agent;
 pub mod entities;
-pub mod sanitize;
+pub mod few_shot;
+pub mod heuristic;
+pub mod policy;
+pub mod registry;
+pub mod verify;
+pub mod vote;
 
-const PROMPT_TEMPLATE: &str = r#"
-I'm looking for the official download URL for source code of the open-source component {{comp_name}}. 
-If it's integrated into a larger project or an official download site isn't accessible, please let me know it's unavailable. 
-If there are several download options, please prioritize official sources like GNU or Coreutils.
-Please Reply with following json format:
-```json
-{
-    "component_name": "<full name of the component>",
-    "available": true,
-    "site_url":"<url of offical download site>"
-}
-```
-For example:
-```json
-{
-    "component_name": "coreutils",
-    "available": true,
-    "site_url":"https://ftp.gnu.org/gnu/coreutils/"
-}
-```
-"#;
-
-fn get_prompt_for_comp(comp_name: &str) -> Result<String> {
-    let reg = handlebars::Handlebars::new();
+fn get_prompt_for_comp(ctx: &Context, comp_name: &str) -> Result<(String, PromptRef)> {
     let data = json!({
     "comp_name": comp_name
     });
-    let prmp = reg.render_template(PROMPT_TEMPLATE, &data)?;
-    Ok(prmp)
+    ctx.prompts().render("discovery", &data)
 }
 
-pub async fn get_download_page(comp_name: &str) -> Result<Option<PageAns>> {
+/// system prompt, few-shot turns from verified pages, then the question for `comp_name`
+fn get_discovery_msgs(ctx: &Context, comp_name: &str) -> Result<(Vec<LLMMsg>, Vec<PromptRef>)> {
+    let (system, sys_prompt) = ctx.prompts().render("discovery_system", &json!({}))?;
+    let mut msgs = vec![LLMMsg::system(&system)];
+    let examples = pick_examples(ctx.verified_pages(), comp_name, ctx.api().get_few_shot());
+    for page in examples {
+        let (query, _) = get_prompt_for_comp(ctx, &page.component_name)?;
+        msgs.push(LLMMsg::user(&query));
+        msgs.push(LLMMsg::assistant(&page.answer_json()));
+    }
+    let (query, prompt) = get_prompt_for_comp(ctx, comp_name)?;
+    msgs.push(LLMMsg::user(&query));
+    Ok((msgs, vec![sys_prompt, prompt]))
+}
+
+pub async fn get_download_page(ctx: &Context, comp_name: &str) -> Result<Option<PageAns>> {
+    if let Some(ovr) = ctx.comp_override(comp_name) {
+        if ovr.skip {
+            log::info!("component {} is skipped by override", comp_name);
+            return Ok(None);
+        }
+        if let Some(url) = ovr.site_url.as_ref() {
+            log::info!("download page of {} is pinned to {}", comp_name, url);
+            return Ok(Some(PageAns::pinned(comp_name, url)));
+        }
+    }
+
+    if ctx.no_llm() {
+        return discover_page(ctx, comp_name).await;
+    }
+    if ctx.registry().enabled
+        && let Some(ans) = registry_page(ctx, comp_name).await?
+    {
+        log::info!("download page of {} found on a registry host", comp_name);
+        return Ok(Some(ans));
+    }
+
     log::info!("query download page url for {}", comp_name);
-    let query = get_prompt_for_comp(comp_name)?;
-    let ans = get_llm_completion(&query).await?;
-    let ans = sanitize_ans(&ans)?;
-    log::debug!("Query Ans: {}", ans);
-    let ans: PageAns = serde_json::from_str(&ans)?;
+    let (ans, citations, prompts) = if ctx.agent().is_enabled() {
+        let (ans, prompts) = agent_download_page(ctx, comp_name).await?;
+        (ans, vec![], prompts)
+    } else {
+        let (msgs, prompts) = get_discovery_msgs(ctx, comp_name)?;
+        let (ans, citations) = if ctx.vote().is_enabled() {
+            vote_download_page(ctx, comp_name, msgs).await?
+        } else {
+            let meta = CallMeta::new(comp_name, Stage::Discovery);
+            get_llm_structured_chat::<PageAns>(ctx, &meta, msgs).await?
+        };
+        (ans, citations, prompts)
+    };
+    let mut ans = ans.with_citations(comp_name, &citations);
+    ans.prompts = prompts;
+    ctx.audit(comp_name, AuditEvent::verdict(Stage::Discovery, None, &ans));
+    log::debug!("Query Ans: {:?}", ans);
     log::info!("query for {} finished", comp_name);
-    ans.refrac_with_valid_url().await
+    ans.refrac_with_valid_url(ctx).await
 }
 
-async fn page_worker(comp_name: &str, semp: &Semaphore) -> Result<Option<PageAns>> {
+async fn page_worker(ctx: &Context, comp_name: &str, semp: &Semaphore) -> Result<Option<PageAns>> {
     let _permit = semp.acquire().await?;
-    let page_ans = get_download_page(comp_name).await?;
+    let page_ans = get_download_page(ctx, comp_name).await?;
     Ok(page_ans)
 }
 
-pub async fn get_download_page_batch(comp_name_list: &[&str]) -> Result<Vec<String>> {
-    let semp = Arc::new(construct_semaphore());
+/// query download pages concurrently, results keep the order of `comp_name_list`
+pub async fn get_download_page_batch(
+    ctx: &Context,
+    comp_name_list: &[&str],
+) -> Result<Vec<(String, Option<PageAns>)>> {
+    let semp = Arc::new(construct_semaphore(ctx.api()));
     let mut hdl_set = vec![];
-    let mut url_list = vec![];
+    let mut page_list = vec![];
 
     for comp_name in comp_name_list.iter() {
         let comp_name = String::from_str(comp_name)?;
         let semp = semp.clone();
-        let hdl = tokio::spawn(async move { page_worker(&comp_name, &semp).await });
+        let ctx = ctx.clone();
+        let hdl = tokio::spawn(async move { page_worker(&ctx, &comp_name, &semp).await });
         hdl_set.push(hdl);
     }
 
-    for hdl in hdl_set {
+    for (comp_name, hdl) in comp_name_list.iter().zip(hdl_set) {
         let res = hdl.await?;
         let res = res?;
-        if let Some(page) = res {
-            let url = page.get_url();
-            url_list.push(url);
-        }
+        page_list.push((comp_name.to_string(), res));
     }
-    Ok(url_list)
+    Ok(page_list)
 }
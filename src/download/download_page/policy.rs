@@ -0,0 +1,260 @@
+//! Which hosts count as an official download site, decided by the `[policy]` section,
+//! the component override and built-in knowledge of forges and mirror networks.
+
+use reqwest::Url;
+use serde::Serialize;
+
+use crate::config::{components::CompOverride, file_config::PolicyConfig};
+
+/// code hosting platforms, a page there is a repository rather than a download site;
+/// only the ones of `policy.deny_forges` are turned down, by default GitHub and GitLab
+const FORGES: [(&str, &[&str]); 6] = [
+    ("GitHub", &["github.com", "githubusercontent.com"]),
+    ("GitLab", &["gitlab.com"]),
+    ("Codeberg", &["codeberg.org"]),
+    ("Bitbucket", &["bitbucket.org"]),
+    ("SourceForge", &["sourceforge.net", "sf.net"]),
+    ("Launchpad", &["launchpad.net"]),
+];
+
+/// mirror networks and archives redistributing upstream releases, besides hosts named `*mirror*`
+const MIRROR_HOSTS: [&str; 10] = [
+    "fossies.org",
+    "ftp.funet.fi",
+    "ftp.fau.de",
+    "ftp.osuosl.org",
+    "rpmfind.net",
+    "pkgs.org",
+    "deb.debian.org",
+    "ftp.debian.org",
+    "archive.ubuntu.com",
+    "download.fedoraproject.org",
+];
+
+/// mirrors run by the upstream project itself, `www.kernel.org/pub` redirects to `mirrors.edge.kernel.org`
+const UPSTREAM_MIRRORS: [&str; 2] = ["ftpmirror.gnu.org", "kernel.org"];
+
+/// rule of the policy that decided on a url
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyRule {
+    /// `deny_hosts` of the component override
+    ComponentDeny,
+    /// `allow_hosts` of the component override
+    ComponentAllow,
+    /// `policy.deny_hosts`
+    Deny,
+    /// `policy.allow_hosts`
+    Allow,
+    Forge,
+    Mirror,
+    /// none of the above, e.g. the project's own site or a self-hosted forge
+    Upstream,
+    /// the url has no host
+    Invalid,
+}
+
+/// whether a url counts as official, along with why
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PolicyVerdict {
+    pub official: bool,
+    pub rule: PolicyRule,
+    pub reason: String,
+}
+
+impl PolicyVerdict {
+    fn new(official: bool, rule: PolicyRule, reason: String) -> Self {
+        Self {
+            official,
+            rule,
+            reason,
+        }
+    }
+}
+
+/// `pattern` is a host, optionally written `*.host`, matching itself and its subdomains
+pub fn host_matches(host: &str, pattern: &str) -> bool {
+    let pattern = pattern.trim().trim_start_matches("*.").to_lowercase();
+    if pattern.is_empty() {
+        return false;
+    }
+    host == pattern || host.ends_with(&format!(".{pattern}"))
+}
+
+fn find_match<'a>(host: &str, patterns: &'a [String]) -> Option<&'a str> {
+    patterns
+        .iter()
+        .find(|pat| host_matches(host, pat))
+        .map(String::as_str)
+}
+
+/// name of the forge hosting `host`, self-hosted instances like `gitlab.gnome.org` are none
+pub fn forge_of(host: &str) -> Option<&'static str> {
+    FORGES
+        .iter()
+        .find(|(_, hosts)| hosts.iter().any(|it| host_matches(host, it)))
+        .map(|(name, _)| *name)
+}
+
+/// names of the known forges, as written in `policy.deny_forges`
+pub fn forge_names() -> Vec<&'static str> {
+    FORGES.iter().map(|(name, _)| *name).collect()
+}
+
+/// mirror network by name, by the built-in list or by `extra`
+pub fn is_mirror(host: &str, extra: &[String]) -> bool {
+    if UPSTREAM_MIRRORS.iter().any(|it| host_matches(host, it)) {
+        return false;
+    }
+    host.split('.').any(|label| label.contains("mirror"))
+        || MIRROR_HOSTS.iter().any(|it| host_matches(host, it))
+        || find_match(host, extra).is_some()
+}
+
+/// Judge the host of `url`: component override first, then the allow and deny lists, forges and mirrors.
+pub fn judge_url(policy: &PolicyConfig, ovr: Option<&CompOverride>, url: &str) -> PolicyVerdict {
+    let host = match Url::parse(url)
+        .ok()
+        .and_then(|it| it.host_str().map(str::to_lowercase))
+    {
+        Some(host) => host,
+        None => {
+            return PolicyVerdict::new(false, PolicyRule::Invalid, "url has no host".to_string());
+        }
+    };
+    let host = host.as_str();
+
+    if let Some(ovr) = ovr {
+        if let Some(pat) = find_match(host, &ovr.deny_hosts) {
+            let reason = format!("{host} matches {pat} in deny_hosts of the component");
+            return PolicyVerdict::new(false, PolicyRule::ComponentDeny, reason);
+        }
+        if let Some(pat) = find_match(host, &ovr.allow_hosts) {
+            let reason = format!("{host} matches {pat} in allow_hosts of the component");
+            return PolicyVerdict::new(true, PolicyRule::ComponentAllow, reason);
+        }
+    }
+    if let Some(pat) = find_match(host, &policy.deny_hosts) {
+        let reason = format!("{host} matches {pat} in policy.deny_hosts");
+        return PolicyVerdict::new(false, PolicyRule::Deny, reason);
+    }
+    if let Some(pat) = find_match(host, &policy.allow_hosts) {
+        let reason = format!("{host} matches {pat} in policy.allow_hosts");
+        return PolicyVerdict::new(true, PolicyRule::Allow, reason);
+    }
+    if let Some(forge) = forge_of(host) {
+        let denied = policy
+            .deny_forges
+            .iter()
+            .any(|it| it.eq_ignore_ascii_case(forge));
+        let reason = if denied {
+            format!("{host} is the code hosting forge {forge} in policy.deny_forges")
+        } else {
+            format!("{host} is the code hosting forge {forge}, not in policy.deny_forges")
+        };
+        return PolicyVerdict::new(!denied, PolicyRule::Forge, reason);
+    }
+    if is_mirror(host, &policy.mirror_hosts) {
+        let reason = format!("{host} is a mirror network");
+        return PolicyVerdict::new(policy.allow_mirrors, PolicyRule::Mirror, reason);
+    }
+    let reason = format!("{host} is neither a forge nor a mirror");
+    PolicyVerdict::new(true, PolicyRule::Upstream, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use color_eyre::eyre::Result;
+
+    fn policy(allow: &[&str], deny: &[&str]) -> PolicyConfig {
+        let to_vec = |hosts: &[&str]| hosts.iter().map(|it| it.to_string()).collect();
+        PolicyConfig {
+            allow_hosts: to_vec(allow),
+            deny_hosts: to_vec(deny),
+            deny_forges: vec!["GitHub".to_string(), "GitLab".to_string()],
+            allow_mirrors: false,
+            mirror_hosts: vec![],
+        }
+    }
+
+    #[test]
+    fn test_default_forges() -> Result<()> {
+        let cfg = crate::config::with_defaults(config::Config::builder())?.build()?;
+        let pol: PolicyConfig = cfg.get("policy")?;
+        let official = |url: &str| judge_url(&pol, None, url).official;
+        // only the forges the url check turned down before the policy existed
+        assert!(!official("https://github.com/a/b/releases"));
+        assert!(!official("https://gitlab.com/a/b/-/releases"));
+        assert!(official("https://sourceforge.net/projects/a/files/"));
+        assert!(official("https://launchpad.net/a/+download"));
+        assert!(official("https://codeberg.org/a/b/releases"));
+        assert!(official("https://bitbucket.org/a/b/downloads/"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_judge_url() {
+        let pol = policy(&["sourceforge.net"], &["*.example.org"]);
+        let rule = |url: &str| {
+            let verdict = judge_url(&pol, None, url);
+            (verdict.official, verdict.rule)
+        };
+        assert_eq!(
+            rule("https://github.com/a/b/releases"),
+            (false, PolicyRule::Forge)
+        );
+        assert_eq!(rule("https://gitlab.com/a/b"), (false, PolicyRule::Forge));
+        // other forges are often the only upstream of a project
+        assert_eq!(
+            rule("https://launchpad.net/a/+download"),
+            (true, PolicyRule::Forge)
+        );
+        assert_eq!(
+            rule("https://codeberg.org/a/b/releases"),
+            (true, PolicyRule::Forge)
+        );
+        // a self-hosted gitlab is the real upstream
+        assert_eq!(
+            rule("https://gitlab.gnome.org/GNOME/glib/"),
+            (true, PolicyRule::Upstream)
+        );
+        assert_eq!(
+            rule("https://mirrors.tuna.tsinghua.edu.cn/gnu/wget/"),
+            (false, PolicyRule::Mirror)
+        );
+        assert_eq!(
+            rule("https://fossies.org/linux/misc/"),
+            (false, PolicyRule::Mirror)
+        );
+        assert_eq!(
+            rule("https://ftpmirror.gnu.org/wget/"),
+            (true, PolicyRule::Upstream)
+        );
+        assert_eq!(
+            rule("https://downloads.sourceforge.net/x/"),
+            (true, PolicyRule::Allow)
+        );
+        assert_eq!(
+            rule("https://www.example.org/x/"),
+            (false, PolicyRule::Deny)
+        );
+        assert_eq!(
+            rule("https://mirrors.edge.kernel.org/pub/linux/utils/util-linux/"),
+            (true, PolicyRule::Upstream)
+        );
+        assert_eq!(rule("not a url"), (false, PolicyRule::Invalid));
+
+        let ovr = CompOverride {
+            allow_hosts: vec!["github.com".to_string()],
+            deny_hosts: vec!["ftp.gnu.org".to_string()],
+            ..Default::default()
+        };
+        let verdict = judge_url(&pol, Some(&ovr), "https://github.com/a/b/releases");
+        assert_eq!(verdict.rule, PolicyRule::ComponentAllow);
+        assert!(verdict.official);
+        let verdict = judge_url(&pol, Some(&ovr), "https://ftp.gnu.org/gnu/a/");
+        assert_eq!(verdict.rule, PolicyRule::ComponentDeny);
+        assert!(verdict.reason.contains("deny_hosts of the component"));
+    }
+}
@@ -0,0 +1,192 @@
+//! Well-known source distribution hosts, probed before the llm is asked.
+
+use color_eyre::eyre::Result;
+use eyre::{WrapErr, bail};
+use regex::Regex;
+use serde::Deserialize;
+
+use super::entities::{CandidateSource, PageAns, PageCandidate};
+use crate::context::Context;
+
+/// libraries released in the shared xorg `lib/` directory
+const XORG_LIBS: &str = r"^lib(X11|Xau|Xdmcp|Xext|Xrender|Xft|Xi|Xrandr|Xfixes|Xcursor|Xinerama|Xtst|Xt|Xmu|Xpm|Xaw|Xcomposite|Xdamage|Xxf86vm|Xv|Xres|Xss|SM|ICE|xcb|xkbfile|xshmfence|pciaccess|fontenc|XScrnSaver)$";
+/// applications released in the shared xorg `app/` directory
+const XORG_APPS: &str = r"^(xrandr|xauth|xinit|xset|xdpyinfo|xev|xkbcomp|xhost|xmodmap|xrdb|xprop|xwininfo|setxkbmap|xkill|xinput|xwd|xsetroot|xdriinfo)$";
+
+/// built-in entries, tried after the ones of `registry.hosts`
+const BUILTIN_ENTRIES: [(&str, Option<&str>); 10] = [
+    ("https://ftp.gnu.org/gnu/{name}/", None),
+    ("https://download.savannah.gnu.org/releases/{name}/", None),
+    ("https://archive.apache.org/dist/{name}/", None),
+    ("https://www.kernel.org/pub/linux/utils/{name}/", None),
+    ("https://www.kernel.org/pub/software/scm/{name}/", None),
+    ("https://www.kernel.org/pub/software/network/{name}/", None),
+    (
+        "https://xorg.freedesktop.org/releases/individual/lib/",
+        Some(XORG_LIBS),
+    ),
+    (
+        "https://xorg.freedesktop.org/releases/individual/app/",
+        Some(XORG_APPS),
+    ),
+    (
+        "https://xorg.freedesktop.org/releases/individual/proto/",
+        Some("^(xorgproto|xcb-proto)$"),
+    ),
+    (
+        "https://xorg.freedesktop.org/releases/individual/xserver/",
+        Some("^xorg-server$"),
+    ),
+];
+
+/// `{name}` of the pattern filled with the name as is, then lowercased
+fn name_urls(pattern: &str, comp_name: &str) -> Vec<String> {
+    let mut urls = vec![pattern.replace("{name}", comp_name)];
+    let lower = pattern.replace("{name}", &comp_name.to_lowercase());
+    if !urls.contains(&lower) {
+        urls.push(lower);
+    }
+    urls
+}
+
+/// a download page pattern of `registry.hosts`
+#[derive(Debug, Clone, Deserialize)]
+pub struct RegistryEntry {
+    /// download page, `{name}` is the component name
+    pub url: String,
+    /// regex the component name has to match, required for a directory shared by many components
+    #[serde(default)]
+    pub names: Option<String>,
+}
+
+impl RegistryEntry {
+    fn builtin((url, names): (&str, Option<&str>)) -> Self {
+        Self {
+            url: url.to_string(),
+            names: names.map(str::to_string),
+        }
+    }
+
+    /// without `{name}` every component would match, so `names` has to narrow it down
+    pub fn check(&self) -> Result<()> {
+        if let Some(names) = self.names.as_ref() {
+            Regex::new(names).wrap_err_with(|| format!("invalid names regex of {}", self.url))?;
+        } else if !self.url.contains("{name}") {
+            bail!("{} has neither `{{name}}` nor `names`", self.url);
+        }
+        Ok(())
+    }
+
+    /// pages of the component under this entry, empty if `names` does not match
+    fn page_urls(&self, comp_name: &str) -> Result<Vec<String>> {
+        if let Some(names) = self.names.as_ref()
+            && !Regex::new(names)?.is_match(comp_name)
+        {
+            return Ok(vec![]);
+        }
+        Ok(name_urls(&self.url, comp_name))
+    }
+}
+
+/// entries of `registry.hosts`, then the built-in ones unless `registry.builtin` is off
+fn registry_entries(ctx: &Context) -> Vec<RegistryEntry> {
+    let registry = ctx.registry();
+    let mut entries = registry.hosts.clone();
+    if registry.builtin {
+        entries.extend(BUILTIN_ENTRIES.into_iter().map(RegistryEntry::builtin));
+    }
+    entries
+}
+
+/// candidate pages of the component on the registry hosts, in registry order
+pub fn registry_candidates(ctx: &Context, comp_name: &str) -> Result<Vec<PageCandidate>> {
+    let mut cands: Vec<PageCandidate> = vec![];
+    for entry in registry_entries(ctx) {
+        for url in entry.page_urls(comp_name)? {
+            if cands.iter().any(|it| it.url == url) {
+                continue;
+            }
+            cands.push(PageCandidate::new(&url, CandidateSource::Registry));
+        }
+    }
+    Ok(cands)
+}
+
+/// Download pages of the component on the registry hosts,
+/// none if no candidate is accessible, official and passes verification.
+pub async fn registry_page(ctx: &Context, comp_name: &str) -> Result<Option<PageAns>> {
+    let cands = registry_candidates(ctx, comp_name)?;
+    if cands.is_empty() {
+        return Ok(None);
+    }
+    log::info!(
+        "probe {} registry pages of {} before asking the llm",
+        cands.len(),
+        comp_name
+    );
+    let ans = PageAns::from_candidates(comp_name, cands)
+        .refrac_with_valid_url(ctx)
+        .await?;
+    Ok(ans.filter(|ans| ans.is_available() && !ans.is_unverified()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mock_llm::{MockPage, MockScript, MockServer},
+        test_util::mock_ctx,
+    };
+
+    #[test]
+    fn test_builtin_entries() -> Result<()> {
+        for entry in BUILTIN_ENTRIES.into_iter().map(RegistryEntry::builtin) {
+            entry.check()?;
+        }
+        let gnu = RegistryEntry::builtin(BUILTIN_ENTRIES[0]);
+        assert_eq!(
+            gnu.page_urls("GnuTLS")?,
+            [
+                "https://ftp.gnu.org/gnu/GnuTLS/",
+                "https://ftp.gnu.org/gnu/gnutls/"
+            ]
+        );
+        assert_eq!(gnu.page_urls("wget")?.len(), 1);
+        let xorg = RegistryEntry::builtin(BUILTIN_ENTRIES[6]);
+        assert_eq!(xorg.page_urls("libX11")?.len(), 1);
+        assert!(xorg.page_urls("libxml2")?.is_empty());
+        let shared = RegistryEntry {
+            url: "https://a.org/dist/".to_string(),
+            names: None,
+        };
+        assert!(shared.check().is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_registry_page() -> Result<()> {
+        let script = MockScript::default().page(MockPage::html(
+            "/releases/foo/",
+            r#"<a href="foo-1.0.tar.gz">foo-1.0.tar.gz</a>"#,
+        ));
+        let server = MockServer::start(script).await?;
+        let registry = format!(
+            "\n[registry]\nhosts = [{{ url = \"{0}/gnu/{{name}}/\" }}, \
+             {{ url = \"{0}/releases/{{name}}/\" }}, {{ url = \"{0}/shared/\", names = \"^bar$\" }}]",
+            server.base_url()
+        );
+        let ctx = mock_ctx(&server, "/nonexistent", &registry)?;
+
+        let cands = registry_candidates(&ctx, "foo")?;
+        assert_eq!(cands.len(), 2);
+        let ans = registry_page(&ctx, "foo").await?.expect("a registry page");
+        assert_eq!(
+            ans.site_url(),
+            Some(format!("{}/releases/foo/", server.base_url()).as_str())
+        );
+        assert_eq!(ans.candidates[0].source, CandidateSource::Registry);
+        assert!(registry_page(&ctx, "baz").await?.is_none());
+        assert!(server.prompts().is_empty());
+        Ok(())
+    }
+}
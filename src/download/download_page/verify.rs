@@ -0,0 +1,215 @@
+//! Content checks of a download page before its links are extracted.
+
+use reqwest::Url;
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    context::Context,
+    download::download_link::{
+        get_all_links, get_page_content,
+        heuristic::{file_name, is_archive_name},
+    },
+};
+
+/// phrases of domain parking pages
+const PARKED_PHRASES: [&str; 6] = [
+    "domain is for sale",
+    "domain may be for sale",
+    "buy this domain",
+    "domain parking",
+    "parked free",
+    "this domain has expired",
+];
+
+/// phrases of titles and headings of login pages
+const LOGIN_PHRASES: [&str; 4] = ["sign in", "log in", "login", "sign on"];
+
+/// why a page failed verification
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PageIssue {
+    /// the page could not be fetched or parsed
+    Unreadable,
+    /// component name in neither the title, the headings nor the link file names
+    NameMissing,
+    NoArchive,
+    Parked,
+    LoginWall,
+    /// root of a site not named after the component
+    GenericHomepage,
+}
+
+/// verification verdict of a download page
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PageVerdict {
+    pub verified: bool,
+    /// where the component name was found: `title`, `heading` or `link`
+    pub name_found_in: Vec<String>,
+    pub archive_links: usize,
+    pub issues: Vec<PageIssue>,
+}
+
+impl PageVerdict {
+    fn unreadable() -> Self {
+        Self {
+            verified: false,
+            name_found_in: vec![],
+            archive_links: 0,
+            issues: vec![PageIssue::Unreadable],
+        }
+    }
+}
+
+/// lowercased text with anything but letters and digits dropped, `util-linux` and `util_linux` are equal
+fn squash(text: &str) -> String {
+    text.chars()
+        .filter(|ch| ch.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn select_texts(doc: &Html, css: &str) -> Vec<String> {
+    let sltr = Selector::parse(css).expect("valid css selector");
+    doc.select(&sltr)
+        .map(|ele| ele.text().collect::<Vec<_>>().join(" "))
+        .collect()
+}
+
+fn contains_any(text: &str, phrases: &[&str]) -> bool {
+    let text = text.to_lowercase();
+    phrases.iter().any(|it| text.contains(it))
+}
+
+/// Check the content of the page at `url`, all checks are local to the fetched html.
+pub fn verify_content(content: &str, url: &str, comp_name: &str) -> PageVerdict {
+    let doc = Html::parse_document(content);
+    let Ok(links) = get_all_links(content, url) else {
+        return PageVerdict::unreadable();
+    };
+    let name = squash(comp_name);
+    let mentions = |text: &str| !name.is_empty() && squash(text).contains(&name);
+
+    let titles = select_texts(&doc, "title");
+    let headings = select_texts(&doc, "h1, h2, h3");
+    let fnames: Vec<String> = links.iter().filter_map(|it| file_name(&it.url)).collect();
+    let mut name_found_in = vec![];
+    if titles.iter().any(|it| mentions(it)) {
+        name_found_in.push("title".to_string());
+    }
+    if headings.iter().any(|it| mentions(it)) {
+        name_found_in.push("heading".to_string());
+    }
+    if fnames.iter().any(|it| mentions(it)) {
+        name_found_in.push("link".to_string());
+    }
+    let archive_links = fnames.iter().filter(|it| is_archive_name(it)).count();
+
+    let mut issues = vec![];
+    if name_found_in.is_empty() {
+        issues.push(PageIssue::NameMissing);
+    }
+    if archive_links == 0 {
+        issues.push(PageIssue::NoArchive);
+    }
+    let body_text = select_texts(&doc, "body").join(" ");
+    if contains_any(&body_text, &PARKED_PHRASES) || contains_any(&titles.join(" "), &PARKED_PHRASES)
+    {
+        issues.push(PageIssue::Parked);
+    }
+    let has_password = doc
+        .select(&Selector::parse("input[type=password]").expect("valid css selector"))
+        .next()
+        .is_some();
+    let login_title = titles
+        .iter()
+        .chain(headings.iter())
+        .any(|it| contains_any(it, &LOGIN_PHRASES));
+    if has_password || (login_title && archive_links == 0) {
+        issues.push(PageIssue::LoginWall);
+    }
+    let is_root = Url::parse(url).is_ok_and(|it| it.path().trim_matches('/').is_empty());
+    let host_named = Url::parse(url)
+        .ok()
+        .and_then(|it| it.host_str().map(squash))
+        .is_some_and(|host| !name.is_empty() && host.contains(&name));
+    if is_root && !host_named && archive_links == 0 {
+        issues.push(PageIssue::GenericHomepage);
+    }
+
+    PageVerdict {
+        verified: issues.is_empty(),
+        name_found_in,
+        archive_links,
+        issues,
+    }
+}
+
+/// Fetch the page and verify its content, an unreadable page is not verified.
+/// The fetched content comes along for link extraction to reuse.
+pub async fn verify_page(
+    ctx: &Context,
+    url: &str,
+    comp_name: &str,
+) -> (PageVerdict, Option<String>) {
+    match get_page_content(ctx, url).await {
+        Ok(content) => (verify_content(&content, url, comp_name), Some(content)),
+        Err(e) => {
+            log::warn!("Failed to fetch {} for verification: {}", url, e);
+            (PageVerdict::unreadable(), None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mock_llm::{MockScript, MockServer},
+        test_util::mock_ctx,
+    };
+    use color_eyre::eyre::Result;
+
+    #[test]
+    fn test_verify_content() {
+        let listing = r#"<html><head><title>Index of /gnu/util-linux</title></head><body>
+<a href="util-linux-2.40.tar.xz">util-linux-2.40.tar.xz</a></body></html>"#;
+        let verdict = verify_content(
+            listing,
+            "https://www.kernel.org/pub/linux/utils/util-linux/",
+            "util_linux",
+        );
+        assert!(verdict.verified, "{verdict:?}");
+        assert_eq!(verdict.name_found_in, ["title", "link"]);
+
+        let parked = r#"<html><head><title>foo.org</title></head>
+<body><h1>This domain is for sale!</h1></body></html>"#;
+        let verdict = verify_content(parked, "https://foo.org/", "foo");
+        assert_eq!(verdict.issues, [PageIssue::NoArchive, PageIssue::Parked]);
+
+        let login = r#"<html><head><title>Sign in</title></head>
+<body><form><input type="password" name="pw"></form></body></html>"#;
+        let verdict = verify_content(login, "https://example.com/", "foo");
+        assert_eq!(
+            verdict.issues,
+            [
+                PageIssue::NameMissing,
+                PageIssue::NoArchive,
+                PageIssue::LoginWall,
+                PageIssue::GenericHomepage
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_missing_page() -> Result<()> {
+        let server = MockServer::start(MockScript::default()).await?;
+        let ctx = mock_ctx(&server, "/nonexistent", "")?;
+        // the 404 page is not taken for the content of the page
+        let (verdict, content) =
+            verify_page(&ctx, &format!("{}/foo/", server.base_url()), "foo").await;
+        assert_eq!(verdict.issues, [PageIssue::Unreadable]);
+        assert!(content.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+use color_eyre::eyre::Result;
+use futures::future::join_all;
+use reqwest::Url;
+
+use super::entities::{PageAns, PageVote};
+use crate::{
+    context::Context,
+    llm_api::{
+        Sample,
+        entities::LLMMsg,
+        get_llm_structured_sample,
+        search::Citation,
+        usage::{CallMeta, Stage},
+    },
+};
+
+/// `host/path` with the host lowercased and without `www.`, scheme, query and trailing `/` dropped
+pub fn normalize_url(url: &str) -> String {
+    let Ok(url_par) = Url::parse(url.trim()) else {
+        return url.trim().trim_end_matches('/').to_lowercase();
+    };
+    let host = url_par.host_str().unwrap_or_default().to_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+    format!("{}{}", host, url_par.path().trim_end_matches('/'))
+}
+
+/// most frequent key along with its count, the earliest one wins a tie
+fn tally(keys: &[Option<String>]) -> (Option<String>, usize) {
+    let mut counts: Vec<(&Option<String>, usize)> = vec![];
+    for key in keys.iter() {
+        match counts.iter_mut().find(|(it, _)| *it == key) {
+            Some((_, cnt)) => *cnt += 1,
+            None => counts.push((key, 1)),
+        }
+    }
+    let mut best: (Option<String>, usize) = (None, 0);
+    for (key, cnt) in counts {
+        if cnt > best.1 {
+            best = (key.clone(), cnt);
+        }
+    }
+    best
+}
+
+/// Ask `vote.samples` times, models taken in turn, and keep the majority answer.
+/// Failed samples count against the agreement; below `vote.min_agreement` the page is abnormal.
+pub async fn vote_download_page(
+    ctx: &Context,
+    comp_name: &str,
+    messages: Vec<LLMMsg>,
+) -> Result<(PageAns, Vec<Citation>)> {
+    let vote = ctx.vote();
+    let meta = CallMeta::new(comp_name, Stage::Discovery);
+    let samples: Vec<Sample> = (0..vote.get_samples())
+        .map(|index| Sample {
+            model: vote.get_model(ctx.api(), index).to_string(),
+            temperature: vote.get_temperature(),
+            index,
+        })
+        .collect();
+    let futs = samples
+        .iter()
+        .map(|sample| get_llm_structured_sample::<PageAns>(ctx, &meta, messages.clone(), sample));
+
+    let mut answers = vec![];
+    let mut last_err = None;
+    for (sample, res) in samples.iter().zip(join_all(futs).await) {
+        match res {
+            Ok((ans, cites)) => answers.push((sample.model.clone(), ans, cites)),
+            Err(e) => {
+                log::warn!(
+                    "sample {} of {} by {} failed: {}",
+                    sample.index,
+                    comp_name,
+                    sample.model,
+                    e
+                );
+                last_err = Some(e);
+            }
+        }
+    }
+    if answers.is_empty() {
+        return Err(last_err.unwrap_or_else(|| eyre::eyre!("no samples for {comp_name}")));
+    }
+
+    let keys: Vec<Option<String>> = answers.iter().map(|(_, ans, _)| ans.vote_key()).collect();
+    let (winner, cnt) = tally(&keys);
+    let agreement = cnt as f64 / samples.len() as f64;
+    let votes = answers
+        .iter()
+        .map(|(model, ans, _)| PageVote {
+            model: model.clone(),
+            site_url: ans.vote_key().and(ans.site_url().map(str::to_string)),
+        })
+        .collect();
+    let idx = keys
+        .iter()
+        .position(|key| *key == winner)
+        .expect("the winner is one of the keys");
+    let (_, mut ans, cites) = answers.swap_remove(idx);
+    log::info!(
+        "{} of {} votes of {} for {:?}",
+        cnt,
+        samples.len(),
+        comp_name,
+        winner
+    );
+    ans.agreement = Some(agreement);
+    ans.votes = votes;
+    if agreement < vote.get_min_agreement() {
+        log::warn!(
+            "low agreement {:.2} on the download page of {}",
+            agreement,
+            comp_name
+        );
+        ans.abnoarmal = true;
+    }
+    Ok((ans, cites))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tally_normalized() {
+        let urls = [
+            Some("https://www.GNU.org/software/wget/"),
+            Some("http://gnu.org/software/wget?lang=en"),
+            None,
+            Some("https://ftp.gnu.org/gnu/wget/"),
+        ];
+        let keys: Vec<Option<String>> = urls.iter().map(|it| it.map(normalize_url)).collect();
+        assert_eq!(keys[0].as_deref(), Some("gnu.org/software/wget"));
+        assert_eq!(tally(&keys), (Some("gnu.org/software/wget".to_string()), 2));
+        // a tie keeps the earliest answer
+        assert_eq!(tally(&keys[2..]), (None, 1));
+    }
+}
@@ -4,33 +4,39 @@ use std::{
     sync::Arc,
 };
 
-use crate::utils::construct_semaphore;
+use crate::{
+    cassette::{Cassette, Exchange},
+    context::Context,
+    utils::construct_semaphore,
+};
 
 use super::download_link::{DLEntryPool, entities::DLEntry};
 use color_eyre::eyre::Result;
 use eyre::bail;
 use futures::StreamExt;
 use path::{create_dir_if_nonexist, get_offical_dl_dir};
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 use tokio::{io::AsyncWriteExt, sync::Semaphore};
 
 pub mod path;
 
 impl DLEntryPool {
-    fn get_comp_dir(&self) -> Result<PathBuf> {
-        let ofi_dir = get_offical_dl_dir()?;
+    fn get_comp_dir(&self, ctx: &Context) -> Result<PathBuf> {
+        let ofi_dir = get_offical_dl_dir(ctx)?;
         let comp_dir = ofi_dir.join(&self.comp_name);
         create_dir_if_nonexist(&comp_dir)?;
         Ok(comp_dir)
     }
-    fn get_download_link_file_path(&self) -> Result<PathBuf> {
-        let comp_dir = self.get_comp_dir()?;
+    fn get_download_link_file_path(&self, ctx: &Context) -> Result<PathBuf> {
+        let comp_dir = self.get_comp_dir(ctx)?;
         let fpath = comp_dir.join("downloadlinks.txt");
         Ok(fpath)
     }
 
-    fn write_download_links(&self) -> Result<()> {
-        let fpath = self.get_download_link_file_path()?;
+    fn write_download_links(&self, ctx: &Context) -> Result<()> {
+        let fpath = self.get_download_link_file_path(ctx)?;
         let mut file = std::fs::File::create(&fpath)?;
 
         for ent in self.entries.iter() {
@@ -39,77 +45,161 @@ impl DLEntryPool {
         Ok(())
     }
 
-    pub async fn download(&self) -> Result<()> {
+    /// save the pool as json, to be fetched later with [`DLEntryPool::load_from`]
+    pub fn save_to(&self, fpath: &Path) -> Result<()> {
+        let file = std::fs::File::create(fpath)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    pub fn load_from(fpath: &Path) -> Result<Self> {
+        let file = std::fs::File::open(fpath)?;
+        let pool = serde_json::from_reader(file)?;
+        Ok(pool)
+    }
+
+    pub async fn download(&self, ctx: &Context) -> Result<Vec<DLOutcome>> {
         if self.is_empty() {
-            return Ok(());
+            return Ok(vec![]);
         }
         let mut hdl_set = vec![];
-        let smph = Arc::new(construct_semaphore());
+        let smph = Arc::new(construct_semaphore(ctx.api()));
         for ent in self.entries.iter() {
             let ent = ent.clone();
             let smph = smph.clone();
-            let hdl = tokio::spawn(async move { ent.download_worker(&smph).await });
+            let ctx = ctx.clone();
+            let hdl = tokio::spawn(async move { ent.download_worker(&ctx, &smph).await });
             hdl_set.push(hdl);
         }
-        for hdl in hdl_set {
-            let _ = hdl.await;
+        let mut outcomes = vec![];
+        for (ent, hdl) in self.entries.iter().zip(hdl_set) {
+            let error = match hdl.await {
+                Ok(Ok(())) => None,
+                Ok(Err(e)) => Some(e.to_string()),
+                Err(e) => Some(e.to_string()),
+            };
+            if let Some(e) = error.as_ref() {
+                log::warn!("Failed to download {}: {}", ent.url, e);
+            }
+            outcomes.push(DLOutcome {
+                fname_ext: ent.fname_ext.clone(),
+                url: ent.url.clone(),
+                error,
+            });
         }
-        self.write_download_links()?;
-        Ok(())
+        self.write_download_links(ctx)?;
+        Ok(outcomes)
     }
 }
 
+/// result of downloading a single entry
+#[derive(Debug, Serialize)]
+pub struct DLOutcome {
+    pub fname_ext: String,
+    pub url: String,
+    pub error: Option<String>,
+}
+
 impl DLEntry {
-    pub async fn download_worker(&self, smph: &Semaphore) -> Result<()> {
+    pub async fn download_worker(&self, ctx: &Context, smph: &Semaphore) -> Result<()> {
         let _permit = smph.acquire().await?;
-        self.download().await?;
+        self.download(ctx).await?;
         Ok(())
     }
 
-    fn get_comp_dir(&self) -> Result<PathBuf> {
-        let ofi_dir = get_offical_dl_dir()?;
+    fn get_comp_dir(&self, ctx: &Context) -> Result<PathBuf> {
+        let ofi_dir = get_offical_dl_dir(ctx)?;
         let comp_dir = ofi_dir.join(&self.comp_name);
         create_dir_if_nonexist(&comp_dir)?;
         Ok(comp_dir)
     }
 
-    fn get_comp_repo_dir(&self) -> Result<PathBuf> {
-        let comp_dir = self.get_comp_dir()?;
+    fn get_comp_repo_dir(&self, ctx: &Context) -> Result<PathBuf> {
+        let comp_dir = self.get_comp_dir(ctx)?;
         let repo_dir = comp_dir.join("repos");
         create_dir_if_nonexist(&repo_dir)?;
         Ok(repo_dir)
     }
 
-    fn get_download_path(&self) -> Result<PathBuf> {
-        let repo_dir = self.get_comp_repo_dir()?;
+    fn get_download_path(&self, ctx: &Context) -> Result<PathBuf> {
+        let repo_dir = self.get_comp_repo_dir(ctx)?;
         Ok(repo_dir.join(&self.fname_ext))
     }
 
-    async fn download(&self) -> Result<()> {
-        let fpath = self.get_download_path()?;
-        Self::download_file(&self.url, &fpath).await?;
+    async fn download(&self, ctx: &Context) -> Result<()> {
+        let cassette = ctx.cassette();
+        if cassette.is_replay() {
+            return Self::replay_download(cassette, &self.url);
+        }
+        let fpath = self.get_download_path(ctx)?;
+        Self::download_file(cassette, &self.url, &fpath).await?;
+        Ok(())
+    }
+
+    /// a replayed download only reports the recorded size and hash, no file is written
+    fn replay_download(cassette: &Cassette, url: &str) -> Result<()> {
+        let Exchange::Download {
+            status,
+            size,
+            sha256,
+            ..
+        } = cassette.take("download", url)?
+        else {
+            unreachable!("exchanges are looked up by kind");
+        };
+        if !StatusCode::from_u16(status)?.is_success() {
+            bail!("Failed to download {}", url);
+        }
+        log::info!(
+            "Replayed download of {}: {} bytes, sha256 {}",
+            url,
+            size,
+            sha256
+        );
         Ok(())
     }
 
-    async fn download_file(url: &str, fpath: &Path) -> Result<()> {
+    async fn download_file(cassette: &Cassette, url: &str, fpath: &Path) -> Result<()> {
         log::info!("Download {} to {:?}", url, fpath);
         let cli = Client::new();
         let resp = cli.get(url).send().await?;
 
-        if !resp.status().is_success() {
+        let status = resp.status();
+        if !status.is_success() {
+            cassette.put(&Exchange::Download {
+                url: url.to_string(),
+                status: status.as_u16(),
+                size: 0,
+                sha256: String::new(),
+            });
             bail!("Failed to download {}", url);
         }
         log::info!("Download {} to {:?}", url, fpath);
 
         let mut file = tokio::fs::File::create(fpath).await?;
         let mut stream = resp.bytes_stream();
+        let mut hasher = Sha256::new();
+        let mut size = 0;
 
         while let Some(chunk) = stream.next().await {
             let chunk = chunk?;
+            hasher.update(&chunk);
+            size += chunk.len() as u64;
             file.write_all(&chunk).await?;
         }
         file.flush().await?;
 
+        let sha256 = hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        cassette.put(&Exchange::Download {
+            url: url.to_string(),
+            status: status.as_u16(),
+            size,
+            sha256,
+        });
         Ok(())
     }
 }
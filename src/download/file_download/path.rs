@@ -6,11 +6,11 @@ use std::{
 use color_eyre::eyre::Result;
 use eyre::bail;
 
-use crate::config::{get_dl_base_dir, get_username};
+use crate::context::Context;
 
-fn get_download_dir() -> Result<PathBuf> {
-    let dir = Path::new(get_dl_base_dir());
-    let uname = get_username();
+fn get_download_dir(ctx: &Context) -> Result<PathBuf> {
+    let dir = Path::new(ctx.get_dl_base_dir());
+    let uname = ctx.get_username();
     let dl_dir = dir.join(uname);
     if !dl_dir.is_dir() {
         bail!(
@@ -21,8 +21,8 @@ fn get_download_dir() -> Result<PathBuf> {
     Ok(dl_dir)
 }
 
-fn get_github_dl_dir() -> Result<PathBuf> {
-    let dl_dir = get_download_dir()?;
+fn get_github_dl_dir(ctx: &Context) -> Result<PathBuf> {
+    let dl_dir = get_download_dir(ctx)?;
     let gh_dir = dl_dir.join("GitHub");
     if !gh_dir.is_dir() {
         bail!(
@@ -33,8 +33,8 @@ fn get_github_dl_dir() -> Result<PathBuf> {
     Ok(gh_dir)
 }
 
-pub fn get_offical_dl_dir() -> Result<PathBuf> {
-    let dl_dir = get_download_dir()?;
+pub fn get_offical_dl_dir(ctx: &Context) -> Result<PathBuf> {
+    let dl_dir = get_download_dir(ctx)?;
     let ofi_dir = dl_dir.join("Official");
     if !ofi_dir.is_dir() {
         bail!(
@@ -45,18 +45,24 @@ pub fn get_offical_dl_dir() -> Result<PathBuf> {
     Ok(ofi_dir)
 }
 
-pub fn get_official_available_page_fpath() -> Result<PathBuf> {
-    let ofi_dir = get_offical_dl_dir()?;
+pub fn get_official_available_page_fpath(ctx: &Context) -> Result<PathBuf> {
+    let ofi_dir = get_offical_dl_dir(ctx)?;
     let fpath = ofi_dir.join("available_url_list.json");
     Ok(fpath)
 }
 
-pub fn get_official_abnormal_page_fpath() -> Result<PathBuf> {
-    let ofi_dir = get_offical_dl_dir()?;
+pub fn get_official_abnormal_page_fpath(ctx: &Context) -> Result<PathBuf> {
+    let ofi_dir = get_offical_dl_dir(ctx)?;
     let fpath = ofi_dir.join("abnormal_url_list.json");
     Ok(fpath)
 }
 
+pub fn get_official_usage_fpath(ctx: &Context) -> Result<PathBuf> {
+    let ofi_dir = get_offical_dl_dir(ctx)?;
+    let fpath = ofi_dir.join("usage.json");
+    Ok(fpath)
+}
+
 fn get_sub_dir_name_list(dir: &Path) -> Result<Vec<String>> {
     let mut name_list: Vec<String> = vec![];
     let entries = fs::read_dir(dir)?;
@@ -88,8 +94,8 @@ pub fn create_dir_if_nonexist(dir: &Path) -> Result<()> {
     Ok(())
 }
 
-pub fn get_download_comp_name_list() -> Result<Vec<String>> {
-    let gh_dir = get_github_dl_dir()?;
+pub fn get_download_comp_name_list(ctx: &Context) -> Result<Vec<String>> {
+    let gh_dir = get_github_dl_dir(ctx)?;
     let res = get_sub_dir_name_list(&gh_dir)?;
     Ok(res)
 }
@@ -3,7 +3,7 @@ pub mod download_page;
 pub mod file_download;
 
 use std::io::{BufWriter, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::{fs, str::FromStr, sync::Arc};
 
 use color_eyre::eyre::Result;
@@ -11,24 +11,34 @@ use download_link::DLEntryPool;
 use download_page::{entities::PageAns, get_download_page};
 use file_download::path::{
     get_download_comp_name_list, get_official_abnormal_page_fpath,
-    get_official_available_page_fpath,
+    get_official_available_page_fpath, get_official_usage_fpath,
 };
 use serde::Serialize;
 use serde_json::Serializer;
 use serde_json::ser::PrettyFormatter;
 use tokio::sync::Semaphore;
 
+use crate::context::Context;
+use crate::llm_api::usage::UsageStat;
 use crate::utils::construct_semaphore;
 
-async fn download_worker(comp_name: &str, smph: &Semaphore) -> Result<Option<PageAns>> {
+async fn download_worker(
+    ctx: &Context,
+    comp_name: &str,
+    smph: &Semaphore,
+) -> Result<Option<PageAns>> {
     let _permit = smph.acquire().await?;
-    let page_ans = get_download_page(comp_name).await?;
+    let page_ans = get_download_page(ctx, comp_name).await?;
     if page_ans.is_none() {
         return Ok(None);
     }
     let mut page = page_ans.unwrap();
-    let dl_pool = DLEntryPool::from_page(&mut page, comp_name).await?;
-    dl_pool.download().await?;
+    if !page.is_available() {
+        // every candidate was rejected, kept for the abnormal list
+        return Ok(Some(page));
+    }
+    let dl_pool = DLEntryPool::from_page(ctx, &mut page, comp_name).await?;
+    dl_pool.download(ctx).await?;
 
     Ok(Some(page))
 }
@@ -49,33 +59,50 @@ fn save_page_json_pretty(page_ans_list: &[PageAns], fpath: &Path) -> Result<()>
     Ok(())
 }
 
-fn save_available_pages(page_ans_list: &[PageAns]) -> Result<()> {
-    let fpath = get_official_available_page_fpath()?;
+fn save_available_pages(ctx: &Context, page_ans_list: &[PageAns]) -> Result<PathBuf> {
+    let fpath = get_official_available_page_fpath(ctx)?;
 
     save_page_json_pretty(page_ans_list, &fpath)?;
-    Ok(())
+    Ok(fpath)
 }
 
-fn save_abnormal_pages(page_ans_list: &[PageAns]) -> Result<()> {
-    let fpath = get_official_abnormal_page_fpath()?;
+fn save_abnormal_pages(ctx: &Context, page_ans_list: &[PageAns]) -> Result<PathBuf> {
+    let fpath = get_official_abnormal_page_fpath(ctx)?;
 
     save_page_json_pretty(page_ans_list, &fpath)?;
-    Ok(())
+    Ok(fpath)
+}
+
+/// outcome of a whole pipeline run
+#[derive(Debug, Serialize)]
+pub struct RunSummary {
+    pub component_count: usize,
+    pub available_count: usize,
+    pub abnormal_count: usize,
+    pub available_fpath: PathBuf,
+    pub abnormal_fpath: PathBuf,
+    /// llm token usage and estimated cost of the run
+    pub usage: UsageStat,
+    pub usage_fpath: PathBuf,
 }
 
-pub async fn download() -> Result<()> {
-    let comp_name_list = get_download_comp_name_list()?;
+pub async fn download(ctx: &Context) -> Result<RunSummary> {
+    let comp_name_list = get_download_comp_name_list(ctx)?;
     log::info!("{} components found", comp_name_list.len());
-    log::info!("example components: {:?}", &comp_name_list[0..5]);
+    log::info!(
+        "example components: {:?}",
+        &comp_name_list[0..comp_name_list.len().min(5)]
+    );
     let mut hdl_set = vec![];
-    let smph = Arc::new(construct_semaphore());
+    let smph = Arc::new(construct_semaphore(ctx.api()));
     let mut page_ans_list = vec![];
     let mut abn_page_ans_list = vec![];
 
     for comp in comp_name_list.iter() {
         let comp_name = String::from_str(comp)?;
         let smph = smph.clone();
-        let hdl = tokio::spawn(async move { download_worker(&comp_name, &smph).await });
+        let ctx = ctx.clone();
+        let hdl = tokio::spawn(async move { download_worker(&ctx, &comp_name, &smph).await });
         hdl_set.push(hdl);
     }
 
@@ -83,15 +110,259 @@ pub async fn download() -> Result<()> {
         let res = hdl.await?;
         let ans_op = res?;
         if let Some(page_ans) = ans_op {
-            page_ans_list.push(page_ans.clone());
+            if page_ans.is_available() {
+                page_ans_list.push(page_ans.clone());
+            }
             if page_ans.abnoarmal {
                 abn_page_ans_list.push(page_ans);
             }
         }
     }
 
-    save_available_pages(&page_ans_list)?;
-    save_abnormal_pages(&abn_page_ans_list)?;
+    let available_fpath = save_available_pages(ctx, &page_ans_list)?;
+    let abnormal_fpath = save_abnormal_pages(ctx, &abn_page_ans_list)?;
+    let usage = ctx.usage().report();
+    let usage_fpath = get_official_usage_fpath(ctx)?;
+    usage.save_to(&usage_fpath)?;
+    log::info!("llm usage has been written to {:?}", usage_fpath);
 
-    Ok(())
+    Ok(RunSummary {
+        component_count: comp_name_list.len(),
+        available_count: page_ans_list.len(),
+        abnormal_count: abn_page_ans_list.len(),
+        available_fpath,
+        abnormal_fpath,
+        usage: usage.total,
+        usage_fpath,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cassette::Cassette,
+        mock_llm::{MockPage, MockRule, MockScript, MockServer},
+        test_util::{foo_base_dir, foo_script, mock_ctx},
+    };
+
+    #[tokio::test]
+    async fn test_download_with_mock() -> Result<()> {
+        let base_dir = foo_base_dir()?;
+        let server = MockServer::start(foo_script()).await?;
+        let ctx = mock_ctx(&server, base_dir.path(), "")?;
+
+        let summary = download(&ctx).await?;
+        assert_eq!(summary.component_count, 1);
+        assert_eq!(summary.available_count, 1);
+        assert_eq!(summary.abnormal_count, 0);
+        // one discovery and one batched classification
+        assert_eq!(server.prompts().len(), 2);
+        let repo_dir = base_dir.path().join("user/Official/foo/repos");
+        assert_eq!(
+            fs::read_to_string(repo_dir.join("foo-1.1.tar.gz"))?,
+            "foo 1.1"
+        );
+        assert!(repo_dir.join("foo-1.0.tar.gz").is_file());
+        assert!(!repo_dir.join("foo-1.1.tar.gz.sig").exists());
+
+        let audit = fs::read_to_string(base_dir.path().join("user/Official/foo/audit.jsonl"))?;
+        let events: Vec<serde_json::Value> = audit
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        let prompts = events.iter().filter(|ev| ev["event"] == "prompt").count();
+        assert_eq!(prompts, 2);
+        let reason_of = |suffix: &str| {
+            events
+                .iter()
+                .find(|ev| {
+                    ev["event"] == "rejected" && ev["url"].as_str().unwrap().ends_with(suffix)
+                })
+                .map(|ev| ev["reason"].clone())
+        };
+        assert_eq!(reason_of(".sig"), Some("filtered".into()));
+        assert_eq!(reason_of("/about.html"), Some("inaccessible".into()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_from_next_candidate() -> Result<()> {
+        let base_dir = foo_base_dir()?;
+        let script = MockScript::default()
+            .rule(MockRule::reply(
+                "official download URL for source code of the open-source component foo",
+                r#"{"component_name": "foo", "available": true, "site_url": "{{base_url}}/latest/",
+                    "alt_urls": ["{{base_url}}/missing/", "{{base_url}}/foo/"]}"#,
+            ))
+            .page(MockPage::html(
+                "/latest/",
+                r#"<title>foo downloads</title><a href="foo-latest.tar.gz">latest</a>"#,
+            ));
+        let script = MockScript {
+            rules: [script.rules, foo_script().rules].concat(),
+            pages: [script.pages, foo_script().pages].concat(),
+        };
+        let server = MockServer::start(script).await?;
+        let ctx = mock_ctx(&server, base_dir.path(), "")?;
+
+        let summary = download(&ctx).await?;
+        assert_eq!(summary.available_count, 1);
+        assert_eq!(summary.abnormal_count, 0);
+        assert!(
+            base_dir
+                .path()
+                .join("user/Official/foo/repos/foo-1.1.tar.gz")
+                .is_file()
+        );
+        let pages: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&summary.available_fpath)?)?;
+        let page = &pages[0];
+        assert_eq!(page["site_url"], format!("{}/foo/", server.base_url()));
+        // the inaccessible alternative is dropped, the verified latest page yields no versions
+        let cands = page["candidates"].as_array().unwrap();
+        assert_eq!(cands.len(), 2);
+        assert_eq!(cands[0]["entries"], 0);
+        assert_eq!(cands[0]["verdict"]["verified"], true);
+        assert_eq!(page["verification"]["archive_links"], 2);
+        assert_eq!(cands[1]["source"], "alternative");
+        assert_eq!(cands[1]["entries"], 2);
+        assert_eq!(page["rejected"][0]["reason"], "inaccessible");
+        assert_eq!(page["rejected"][0]["detail"], "HEAD 404, GET 404");
+        assert_eq!(cands[0]["access"]["status"], 200);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_all_rejected() -> Result<()> {
+        let base_dir = foo_base_dir()?;
+        let script = MockScript::default().rule(MockRule::reply(
+            "official download URL for source code of the open-source component foo",
+            r#"{"component_name": "foo", "available": true, "site_url": "{{base_url}}/gone/"}"#,
+        ));
+        let server = MockServer::start(script).await?;
+        let ctx = mock_ctx(&server, base_dir.path(), "")?;
+
+        let summary = download(&ctx).await?;
+        assert_eq!(summary.available_count, 0);
+        assert_eq!(summary.abnormal_count, 1);
+        let pages: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&summary.abnormal_fpath)?)?;
+        let page = &pages[0];
+        assert_eq!(page["available"], false);
+        assert_eq!(page["rejected"][0]["reason"], "inaccessible");
+        assert_eq!(
+            page["rejected"][0]["url"],
+            format!("{}/gone/", server.base_url())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_low_agreement_on_unreachable_page() -> Result<()> {
+        let base_dir = foo_base_dir()?;
+        let discovery = "official download URL for source code of the open-source component foo";
+        let script = MockScript::default()
+            .rule(
+                MockRule::reply(
+                    discovery,
+                    r#"{"component_name": "foo", "available": true, "site_url": "{{base_url}}/gone/"}"#,
+                )
+                .times(2),
+            )
+            .rule(MockRule::reply(
+                discovery,
+                r#"{"component_name": "foo", "available": true, "site_url": "{{base_url}}/foo/"}"#,
+            ));
+        let server = MockServer::start(script).await?;
+        let ctx = mock_ctx(
+            &server,
+            base_dir.path(),
+            "\n[vote]\nsamples = 3\nmin_agreement = 0.9",
+        )?;
+
+        let summary = download(&ctx).await?;
+        assert_eq!(summary.available_count, 0);
+        assert_eq!(summary.abnormal_count, 1);
+        let pages: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&summary.abnormal_fpath)?)?;
+        let page = &pages[0];
+        let agreement = page["agreement"].as_f64().unwrap();
+        assert!((agreement - 2.0 / 3.0).abs() < 1e-9, "{agreement}");
+        assert_eq!(page["votes"].as_array().unwrap().len(), 3);
+        assert_eq!(page["rejected"][0]["reason"], "inaccessible");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_without_llm() -> Result<()> {
+        let base_dir = foo_base_dir()?;
+        let server = MockServer::start(foo_script()).await?;
+        let heuristic = format!(
+            "[heuristic]\nenabled = true\n[registry]\nhosts = [{{ url = \"{0}/gnu/{{name}}/\" }}, {{ url = \"{0}/{{name}}/\" }}]",
+            server.base_url()
+        );
+        let ctx = mock_ctx(&server, base_dir.path(), &heuristic)?;
+
+        let summary = download(&ctx).await?;
+        assert_eq!(summary.available_count, 1);
+        assert_eq!(summary.abnormal_count, 0);
+        assert!(server.prompts().is_empty());
+        let repo_dir = base_dir.path().join("user/Official/foo/repos");
+        assert!(repo_dir.join("foo-1.0.tar.gz").is_file());
+        assert!(repo_dir.join("foo-1.1.tar.gz").is_file());
+        assert!(!repo_dir.join("foo-1.1.tar.gz.sig").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_listed_page_skips_policy() -> Result<()> {
+        let base_dir = foo_base_dir()?;
+        let server = MockServer::start(foo_script()).await?;
+        let page_list = base_dir.path().join("pages.toml");
+        fs::write(&page_list, format!("foo = \"{}/foo/\"", server.base_url()))?;
+        // the listed page is on a denied host, like a listed forge page
+        let extra = format!(
+            "[heuristic]\nenabled = true\npage_list = {:?}\n[policy]\ndeny_hosts = [\"127.0.0.1\"]",
+            page_list.to_string_lossy()
+        );
+        let ctx = mock_ctx(&server, base_dir.path(), &extra)?;
+
+        let summary = download(&ctx).await?;
+        assert_eq!(summary.available_count, 1);
+        assert_eq!(summary.abnormal_count, 0);
+        let repo_dir = base_dir.path().join("user/Official/foo/repos");
+        assert!(repo_dir.join("foo-1.1.tar.gz").is_file());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replay_without_network() -> Result<()> {
+        let rec_dir = foo_base_dir()?;
+        let cassette_fpath = rec_dir.path().join("run.jsonl");
+        let server = MockServer::start(foo_script()).await?;
+        let ctx = mock_ctx(&server, rec_dir.path(), "")?
+            .with_cassette(Cassette::record(&cassette_fpath)?);
+        download(&ctx).await?;
+        drop(ctx);
+        drop(server);
+        // the page fetched for verification is reused for link extraction
+        let gets = fs::read_to_string(&cassette_fpath)?
+            .lines()
+            .filter(|line| line.contains(r#""kind":"get""#))
+            .count();
+        assert_eq!(gets, 1);
+
+        let replay_dir = foo_base_dir()?;
+        let dead = MockServer::start(MockScript::default()).await?;
+        let ctx = mock_ctx(&dead, replay_dir.path(), "")?
+            .with_cassette(Cassette::replay(&cassette_fpath)?);
+        let summary = download(&ctx).await?;
+        assert_eq!(summary.available_count, 1);
+        assert!(dead.prompts().is_empty());
+        // replayed downloads leave the real output alone
+        assert!(!replay_dir.path().join("user/Official/foo/repos").exists());
+        assert_eq!(ctx.usage().report().total.calls, 2);
+        Ok(())
+    }
 }
@@ -1,4 +1,12 @@
+pub mod audit;
+pub mod cassette;
+pub mod commands;
 pub mod config;
+pub mod context;
 pub mod download;
 pub mod llm_api;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock_llm;
+#[cfg(test)]
+pub(crate) mod test_util;
 pub mod utils;
@@ -0,0 +1,243 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use color_eyre::eyre::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{
+    config::{LlmCacheConfig, ProviderKind},
+    entities::LLMMsg,
+    provider::{Completion, CompletionReq},
+    search::Citation,
+};
+
+/// default cache dir, relative to the download base dir
+const CACHE_DIRNAME: &str = ".llm_cache";
+
+/// how the cache is used in this run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    /// answer from the cache, store new answers
+    ReadWrite,
+    /// ignore cached answers, store new ones (`--refresh`)
+    Refresh,
+    /// neither read nor write (`--no-cache`)
+    Off,
+}
+
+/// everything that determines an answer
+#[derive(Serialize)]
+struct CacheKey<'a> {
+    provider: ProviderKind,
+    /// `api.api_url`, the same model name may be served differently by another endpoint
+    #[serde(skip_serializing_if = "Option::is_none")]
+    endpoint: Option<&'a str>,
+    model: &'a str,
+    temperature: f64,
+    messages: &'a [LLMMsg],
+    json_schema: Option<&'a serde_json::Value>,
+    /// left out for the first sample, keeping keys of single answers stable
+    #[serde(skip_serializing_if = "is_first_sample")]
+    sample: usize,
+}
+
+fn is_first_sample(sample: &usize) -> bool {
+    *sample == 0
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    /// unix timestamp in seconds
+    created_at: u64,
+    model: String,
+    content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    citations: Vec<Citation>,
+}
+
+/// Content addressed llm answers, one json file per request.
+pub struct LlmCache {
+    dir: PathBuf,
+    ttl: Option<Duration>,
+    mode: CacheMode,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl LlmCache {
+    pub fn new(cfg: &LlmCacheConfig, base_dir: &str, mode: CacheMode) -> Self {
+        let dir = match cfg.get_dir() {
+            Some(dir) => PathBuf::from(dir),
+            None => Path::new(base_dir).join(CACHE_DIRNAME),
+        };
+        let mode = if cfg.is_enabled() {
+            mode
+        } else {
+            CacheMode::Off
+        };
+        Self {
+            dir,
+            ttl: cfg.get_ttl(),
+            mode,
+        }
+    }
+
+    pub fn mode(&self) -> CacheMode {
+        self.mode
+    }
+
+    /// Sha256 over provider, endpoint, model, temperature, the rendered messages and the sample index.
+    /// Cassettes leave the endpoint out, so that a run replays against any of them.
+    pub fn key(
+        provider: ProviderKind,
+        endpoint: Option<&str>,
+        req: &CompletionReq,
+    ) -> Result<String> {
+        let key = CacheKey {
+            provider,
+            endpoint,
+            model: &req.model,
+            temperature: req.temperature,
+            messages: &req.messages,
+            json_schema: req.json_schema.as_ref().map(|spec| &spec.schema),
+            sample: req.sample,
+        };
+        let digest = Sha256::digest(serde_json::to_vec(&key)?);
+        Ok(digest.iter().map(|b| format!("{b:02x}")).collect())
+    }
+
+    fn entry_fpath(&self, key: &str) -> PathBuf {
+        self.dir.join(&key[..2]).join(format!("{key}.json"))
+    }
+
+    /// cached answer, if present and not expired, token usage is not kept
+    pub async fn get(&self, key: &str) -> Option<Completion> {
+        if self.mode != CacheMode::ReadWrite {
+            return None;
+        }
+        let text = tokio::fs::read_to_string(self.entry_fpath(key))
+            .await
+            .ok()?;
+        let ent: CacheEntry = match serde_json::from_str(&text) {
+            Ok(ent) => ent,
+            Err(e) => {
+                log::warn!("Corrupted llm cache entry {}: {}", key, e);
+                return None;
+            }
+        };
+        if let Some(ttl) = self.ttl
+            && now_secs().saturating_sub(ent.created_at) > ttl.as_secs()
+        {
+            return None;
+        }
+        Some(Completion {
+            content: ent.content,
+            usage: None,
+            citations: ent.citations,
+        })
+    }
+
+    pub async fn put(&self, key: &str, model: &str, ans: &Completion) -> Result<()> {
+        if self.mode == CacheMode::Off {
+            return Ok(());
+        }
+        let fpath = self.entry_fpath(key);
+        if let Some(dir) = fpath.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let ent = CacheEntry {
+            created_at: now_secs(),
+            model: model.to_string(),
+            content: ans.content.clone(),
+            citations: ans.citations.clone(),
+        };
+        tokio::fs::write(&fpath, serde_json::to_vec_pretty(&ent)?).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm_api::entities::AllowedRole;
+
+    fn test_req(query: &str) -> CompletionReq {
+        CompletionReq {
+            model: "test-model".to_string(),
+            temperature: 0.1,
+            messages: vec![LLMMsg {
+                role: AllowedRole::USER,
+                content: query.to_string(),
+            }],
+            json_schema: None,
+            sample: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cache_roundtrip() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path().to_path_buf();
+        let cache = |mode| LlmCache {
+            dir: dir.clone(),
+            ttl: None,
+            mode,
+        };
+        let endpoint = Some("https://api.a.com/v1/chat/completions");
+        let key = LlmCache::key(ProviderKind::OpenAI, endpoint, &test_req("a"))?;
+        assert_ne!(
+            key,
+            LlmCache::key(ProviderKind::OpenAI, endpoint, &test_req("b"))?
+        );
+        assert_ne!(
+            key,
+            LlmCache::key(ProviderKind::Ollama, endpoint, &test_req("a"))?
+        );
+        let other = Some("https://api.b.com/v1/chat/completions");
+        assert_ne!(
+            key,
+            LlmCache::key(ProviderKind::OpenAI, other, &test_req("a"))?
+        );
+        let resample = CompletionReq {
+            sample: 1,
+            ..test_req("a")
+        };
+        assert_ne!(
+            key,
+            LlmCache::key(ProviderKind::OpenAI, endpoint, &resample)?
+        );
+
+        let ans = |content: &str| Completion {
+            content: content.to_string(),
+            usage: None,
+            citations: vec![],
+        };
+        cache(CacheMode::Off)
+            .put(&key, "test-model", &ans("no"))
+            .await?;
+        assert!(cache(CacheMode::ReadWrite).get(&key).await.is_none());
+
+        cache(CacheMode::ReadWrite)
+            .put(&key, "test-model", &ans("yes"))
+            .await?;
+        assert_eq!(
+            cache(CacheMode::ReadWrite)
+                .get(&key)
+                .await
+                .map(|it| it.content)
+                .as_deref(),
+            Some("yes")
+        );
+        assert!(cache(CacheMode::Refresh).get(&key).await.is_none());
+
+        Ok(())
+    }
+}
@@ -1,60 +1,235 @@
-use crate::config::load_config;
-use serde::Deserialize;
+use std::{collections::HashMap, time::Duration};
 
-#[derive(Deserialize)]
+use serde::{Deserialize, Serialize};
+
+use super::usage::ModelPrice;
+
+/// request shape of the llm api
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ProviderKind {
+    /// chat-completions api of OpenAI and compatible servers
+    #[serde(rename = "openai")]
+    OpenAI,
+    /// messages api of Anthropic
+    #[serde(rename = "anthropic")]
+    Anthropic,
+    /// `/api/chat` of a local ollama server
+    #[serde(rename = "ollama")]
+    Ollama,
+}
+
+impl ProviderKind {
+    pub const NAMES: [&str; 3] = ["openai", "anthropic", "ollama"];
+}
+
+#[derive(Clone, Deserialize)]
 pub struct ApiConfig {
+    provider: ProviderKind,
     key: String,
     model_id: String,
     api_url: String,
     temperature: f64,
+    max_tokens: usize,
+    /// ask for structured output where the provider supports it, dropped after a 400 naming `response_format`
+    json_schema: bool,
     parallel: usize,
     retry: usize,
     check_retry: u64,
+    /// base seconds of the exponential backoff between retries
     retry_delay: usize,
+    /// upper bound of a single backoff, a longer `Retry-After` of the server is cut to it
+    max_retry_delay: usize,
+    /// verified pages of earlier runs shown as examples in discovery, 0 for none
+    few_shot: usize,
+    /// links classified per llm call, 0 asks two yes/no questions per link instead
+    link_batch_size: usize,
+    /// requests per minute, 0 for no limit
+    rpm: u64,
+    /// prompt plus completion tokens per minute, 0 for no limit
+    tpm: u64,
     time_out: usize,
+    /// seconds a single llm request may take, timeouts are retried
+    llm_time_out: u64,
+    /// usd per million tokens keyed by model id, for the cost estimate of a run
+    #[serde(default)]
+    prices: HashMap<String, ModelPrice>,
 }
 
-pub fn get_temperature() -> &'static f64 {
-    let config = load_config();
-    &config.api.temperature
-}
+impl ApiConfig {
+    pub fn get_provider(&self) -> ProviderKind {
+        self.provider
+    }
+
+    pub fn get_max_tokens(&self) -> usize {
+        self.max_tokens
+    }
+
+    pub fn get_json_schema(&self) -> bool {
+        self.json_schema
+    }
+
+    pub fn get_temperature(&self) -> f64 {
+        self.temperature
+    }
+
+    pub fn get_api_key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn get_api_url(&self) -> &str {
+        &self.api_url
+    }
+
+    pub fn get_model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    pub fn get_parralel_count(&self) -> usize {
+        self.parallel
+    }
+
+    pub fn get_api_retry(&self) -> usize {
+        self.retry
+    }
+
+    pub fn get_api_retry_delay(&self) -> usize {
+        self.retry_delay
+    }
+
+    pub fn get_api_max_retry_delay(&self) -> usize {
+        self.max_retry_delay
+    }
 
-pub fn get_api_key() -> &'static str {
-    let config = load_config();
-    &config.api.key
+    pub fn get_few_shot(&self) -> usize {
+        self.few_shot
+    }
+
+    pub fn get_link_batch_size(&self) -> usize {
+        self.link_batch_size
+    }
+
+    pub fn get_rpm(&self) -> u64 {
+        self.rpm
+    }
+
+    pub fn get_tpm(&self) -> u64 {
+        self.tpm
+    }
+
+    pub fn get_api_timeout(&self) -> usize {
+        self.time_out
+    }
+
+    pub fn get_llm_timeout(&self) -> u64 {
+        self.llm_time_out
+    }
+
+    pub fn get_prices(&self) -> &HashMap<String, ModelPrice> {
+        &self.prices
+    }
+
+    pub fn get_api_check_retry(&self) -> u64 {
+        self.check_retry
+    }
 }
 
-pub fn get_api_url() -> &'static str {
-    let config = load_config();
-    &config.api.api_url
+/// `[cache]` section, on-disk cache of llm answers
+#[derive(Clone, Deserialize)]
+pub struct LlmCacheConfig {
+    enabled: bool,
+    /// seconds an answer stays valid, 0 keeps it forever
+    ttl: u64,
+    /// cache dir, defaults to `.llm_cache` under the download base dir
+    dir: String,
 }
 
-pub fn get_model_id() -> &'static str {
-    let config = load_config();
-    &config.api.model_id
+impl LlmCacheConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn get_ttl(&self) -> Option<Duration> {
+        (self.ttl > 0).then(|| Duration::from_secs(self.ttl))
+    }
+
+    pub fn get_dir(&self) -> Option<&str> {
+        (!self.dir.is_empty()).then_some(self.dir.as_str())
+    }
 }
 
-pub fn get_parralel_count() -> usize {
-    let config = load_config();
-    config.api.parallel
+/// `[prompts]` section, templates overriding the built-in prompts
+#[derive(Clone, Deserialize)]
+pub struct PromptsConfig {
+    /// dir of `<name>.hbs` files, missing files fall back to the built-ins
+    pub dir: String,
 }
 
-pub fn get_api_retry() -> usize {
-    let config = load_config();
-    config.api.retry
+/// `[vote]` section, self-consistency voting on download page discovery
+#[derive(Clone, Deserialize)]
+pub struct VoteConfig {
+    /// answers asked per component, 1 turns voting off
+    samples: usize,
+    /// models asked in turn, empty for `api.model_id` only; all served by `api.api_url`
+    #[serde(default)]
+    models: Vec<String>,
+    /// sampling temperature of the votes
+    temperature: f64,
+    /// share of votes the majority needs, below it the page is marked abnormal
+    min_agreement: f64,
 }
 
-pub fn get_api_retry_delay() -> usize {
-    let config = load_config();
-    config.api.retry_delay
+impl VoteConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.samples > 1
+    }
+
+    pub fn get_samples(&self) -> usize {
+        self.samples
+    }
+
+    /// model of the `idx`-th sample, round robin over `models`
+    pub fn get_model<'a>(&'a self, api: &'a ApiConfig, idx: usize) -> &'a str {
+        if self.models.is_empty() {
+            return api.get_model_id();
+        }
+        &self.models[idx % self.models.len()]
+    }
+
+    pub fn get_temperature(&self) -> f64 {
+        self.temperature
+    }
+
+    pub fn get_min_agreement(&self) -> f64 {
+        self.min_agreement
+    }
 }
 
-pub fn get_api_timeout() -> usize {
-    let config = load_config();
-    config.api.time_out
+/// `[agent]` section, discovery by a model browsing with tools before it answers
+#[derive(Clone, Deserialize)]
+pub struct AgentConfig {
+    enabled: bool,
+    /// tool calls allowed per component, the model has to answer after the last one
+    max_tool_calls: usize,
+    /// page text or link list handed back to the model is cut to this many chars
+    max_page_chars: usize,
+    /// let the tools open loopback and private addresses, e.g. for a local mirror
+    allow_private_hosts: bool,
 }
 
-pub fn get_api_check_retry() -> u64 {
-    let config = load_config();
-    config.api.check_retry
+impl AgentConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn get_max_tool_calls(&self) -> usize {
+        self.max_tool_calls
+    }
+
+    pub fn get_max_page_chars(&self) -> usize {
+        self.max_page_chars
+    }
+
+    pub fn get_allow_private_hosts(&self) -> bool {
+        self.allow_private_hosts
+    }
 }
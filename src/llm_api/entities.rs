@@ -1,28 +1,62 @@
 use color_eyre::eyre::Result;
+use eyre::bail;
 use std::fmt::Display;
 
 use serde::{Deserialize, Serialize};
 
+use super::{
+    search::{ActionDetail, Citation, Reference, rank_citations},
+    usage::TokenUsage,
+};
+
 /// request json spec
 #[derive(Serialize)]
 pub struct ReqBody {
     pub model: String,
     pub temperature: f64,
     pub messages: Vec<LLMMsg>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<serde_json::Value>,
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct LLMMsg {
     pub role: AllowedRole,
     pub content: String,
 }
 
-#[derive(Serialize)]
+impl LLMMsg {
+    pub fn system(content: &str) -> Self {
+        Self {
+            role: AllowedRole::SYSTEM,
+            content: content.to_string(),
+        }
+    }
+
+    pub fn user(content: &str) -> Self {
+        Self {
+            role: AllowedRole::USER,
+            content: content.to_string(),
+        }
+    }
+
+    /// an earlier answer of the model, e.g. a few-shot example
+    pub fn assistant(content: &str) -> Self {
+        Self {
+            role: AllowedRole::ASSISTANT,
+            content: content.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub enum AllowedRole {
     #[serde(rename = "user")]
     USER,
     #[serde(rename = "system")]
     SYSTEM,
+    #[serde(rename = "assistant")]
+    ASSISTANT,
 }
 
 /// response json spec
@@ -31,6 +65,30 @@ pub struct RespBody {
     pub id: String,
     pub model: String,
     pub choices: Vec<ChoiceObj>,
+    #[serde(default)]
+    pub usage: Option<TokenUsage>,
+    /// search models report usage per underlying model here instead of `usage`
+    #[serde(default)]
+    pub bot_usage: Option<BotUsage>,
+    /// pages cited by search models
+    #[serde(default)]
+    pub references: Vec<Reference>,
+}
+
+#[derive(Deserialize)]
+pub struct BotUsage {
+    #[serde(default)]
+    pub model_usage: Vec<ModelUsage>,
+    /// plugin calls of search models, e.g. web search results
+    #[serde(default)]
+    pub action_details: Vec<ActionDetail>,
+}
+
+#[derive(Deserialize)]
+pub struct ModelUsage {
+    pub name: String,
+    #[serde(flatten)]
+    pub tokens: TokenUsage,
 }
 
 #[derive(Deserialize)]
@@ -48,8 +106,34 @@ pub struct RespMsg {
 
 impl RespBody {
     pub fn get_content(&self) -> Result<String> {
-        assert!(!self.choices.is_empty(), "No available choices: {}", self);
-        Ok(self.choices[0].message.content.clone())
+        match self.choices.first() {
+            Some(choice) => Ok(choice.message.content.clone()),
+            None => bail!("No available choices: {}", self),
+        }
+    }
+
+    /// search results the answer is based on, empty for plain chat models
+    pub fn get_citations(&self) -> Vec<Citation> {
+        match self.bot_usage.as_ref() {
+            Some(bot) => rank_citations(&bot.action_details, &self.references),
+            None => vec![],
+        }
+    }
+
+    /// `usage`, or the sum over `bot_usage.model_usage` for search models
+    pub fn get_usage(&self) -> Option<TokenUsage> {
+        if let Some(usage) = self.usage {
+            return Some(usage);
+        }
+        let model_usage = &self.bot_usage.as_ref()?.model_usage;
+        if model_usage.is_empty() {
+            return None;
+        }
+        let mut total = TokenUsage::default();
+        for it in model_usage.iter() {
+            total += it.tokens;
+        }
+        Some(total)
     }
 }
 
@@ -67,3 +151,24 @@ impl Display for RespBody {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_resp_usage() -> Result<()> {
+        let resp: RespBody = serde_json::from_str(include_str!("../../resp.json"))?;
+        let usage = resp.get_usage().expect("bot_usage of search models");
+        assert_eq!(usage.prompt_tokens, 2490);
+        assert_eq!(usage.completion_tokens, 374);
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_choices() -> Result<()> {
+        let resp: RespBody = serde_json::from_str(r#"{"id": "1", "model": "m", "choices": []}"#)?;
+        assert!(resp.get_content().is_err());
+        Ok(())
+    }
+}
@@ -1,76 +1,247 @@
-use config::{
-    get_api_key, get_api_retry, get_api_retry_delay, get_api_url, get_model_id, get_temperature,
-};
-use entities::{LLMMsg, ReqBody, RespBody};
-use eyre::bail;
-use reqwest::header::{HeaderMap, HeaderValue};
-use search::{is_search_api, show_search_resp_content};
-use std::sync::OnceLock;
-
+use cache::LlmCache;
 use color_eyre::eyre::Result;
-use reqwest::{
-    Client, ClientBuilder,
-    header::{AUTHORIZATION, CONTENT_TYPE},
-};
+use entities::LLMMsg;
+use provider::{Completion, CompletionReq, JsonSchemaSpec};
+use sanitize::parse_ans;
+use schemars::JsonSchema;
+use search::Citation;
+use serde::de::DeserializeOwned;
+use usage::{CallMeta, Stage};
 
-use crate::utils::post_with_retry;
+use crate::{
+    audit::{AnswerSource, AuditEvent},
+    cassette::Exchange,
+    context::Context,
+};
 
+pub mod cache;
 pub mod config;
 pub mod entities;
+pub mod prompts;
+pub mod provider;
+pub mod rate_limit;
+pub mod sanitize;
 pub mod search;
+pub mod usage;
 
-pub async fn get_llm_completion(query: &str) -> Result<String> {
-    let model_id = get_model_id();
-    let tmptr = get_temperature();
-    let payload = ReqBody {
-        model: model_id.to_owned(),
-        temperature: *tmptr,
-        messages: vec![LLMMsg {
-            role: entities::AllowedRole::USER,
-            content: query.to_owned(),
-        }],
-    };
+fn chat_req(
+    ctx: &Context,
+    messages: Vec<LLMMsg>,
+    json_schema: Option<JsonSchemaSpec>,
+) -> CompletionReq {
+    let api = ctx.api();
+    CompletionReq {
+        model: api.get_model_id().to_owned(),
+        temperature: api.get_temperature(),
+        messages,
+        json_schema,
+        sample: 0,
+    }
+}
 
-    let client = get_llm_api_client();
-    let url = get_api_url();
-    let resp = post_with_retry(
-        client,
-        url,
-        &payload,
-        get_api_retry(),
-        get_api_retry_delay(),
-    )
-    .await?;
-
-    #[cfg(debug_assertions)]
-    {
-        if is_search_api() {
-            show_search_resp_content(resp).await?;
-            bail!("Stopped for search api");
-        }
+/// Send a request, answering from the on-disk cache when possible, and book its token usage.
+/// With a cassette, the answer is recorded or replayed without asking the api.
+async fn complete_cached(
+    ctx: &Context,
+    meta: &CallMeta,
+    req: &CompletionReq,
+) -> Result<Completion> {
+    if ctx.no_llm() {
+        eyre::bail!("llm calls are disabled in heuristic mode (`--no-llm`)");
+    }
+    let tape_key = LlmCache::key(ctx.llm().kind(), None, req)?;
+    let cassette = ctx.cassette();
+    if cassette.is_replay() {
+        let Exchange::Llm {
+            content,
+            usage,
+            citations,
+            ..
+        } = cassette.take("llm", &tape_key)?
+        else {
+            unreachable!("exchanges are looked up by kind");
+        };
+        ctx.usage().record(meta, &req.model, usage);
+        let ans = Completion {
+            content,
+            usage,
+            citations,
+        };
+        audit_prompt(ctx, meta, req, &ans, AnswerSource::Cassette);
+        return Ok(ans);
     }
 
-    let resp_json: RespBody = resp.json().await?;
-    let ans = resp_json.get_content()?;
+    let key = LlmCache::key(ctx.llm().kind(), Some(ctx.api().get_api_url()), req)?;
+    let cache = ctx.llm_cache();
+    let (ans, source) = match cache.get(&key).await {
+        Some(ans) => {
+            log::debug!("llm cache hit: {}", key);
+            ctx.usage().record_cached(meta, &req.model);
+            (ans, AnswerSource::Cache)
+        }
+        None => {
+            let ans = ctx.llm().complete(req).await?;
+            ctx.usage().record(meta, &req.model, ans.usage);
+            if let Err(e) = cache.put(&key, &req.model, &ans).await {
+                log::warn!("Failed to cache llm answer {}: {}", key, e);
+            }
+            (ans, AnswerSource::Api)
+        }
+    };
+    cassette.put(&Exchange::llm(&tape_key, &req.model, &ans));
+    audit_prompt(ctx, meta, req, &ans, source);
     Ok(ans)
 }
 
-fn get_llm_api_client() -> &'static Client {
-    static CLIENT: OnceLock<Client> = OnceLock::new();
-    CLIENT.get_or_init(|| match construct_llm_api_client() {
-        Ok(cli) => cli,
+/// prompts of pipeline stages go to the audit trail of the component
+fn audit_prompt(
+    ctx: &Context,
+    meta: &CallMeta,
+    req: &CompletionReq,
+    ans: &Completion,
+    source: AnswerSource,
+) {
+    if meta.stage == Stage::Adhoc {
+        return;
+    }
+    let event = AuditEvent::Prompt {
+        stage: meta.stage,
+        model: req.model.clone(),
+        messages: req.messages.clone(),
+        answer: ans.content.clone(),
+        source,
+    };
+    ctx.audit(&meta.component, event);
+}
+
+pub async fn get_llm_completion(ctx: &Context, meta: &CallMeta, query: &str) -> Result<String> {
+    get_llm_chat(ctx, meta, vec![LLMMsg::user(query)]).await
+}
+
+/// Answer to a conversation of system, user and assistant messages, the last one usually from the user.
+pub async fn get_llm_chat(ctx: &Context, meta: &CallMeta, messages: Vec<LLMMsg>) -> Result<String> {
+    let req = chat_req(ctx, messages, None);
+    let ans = complete_cached(ctx, meta, &req).await?;
+    Ok(ans.content)
+}
+
+/// Ask for an answer of type `T`, using the provider's json schema mode when available.
+/// An unparsable answer gets one repair request before giving up.
+pub async fn get_llm_structured<T: JsonSchema + DeserializeOwned>(
+    ctx: &Context,
+    meta: &CallMeta,
+    query: &str,
+) -> Result<T> {
+    let (val, _) = get_llm_structured_cited(ctx, meta, query).await?;
+    Ok(val)
+}
+
+/// [`get_llm_structured`] along with the search results a search model based its answer on
+pub async fn get_llm_structured_cited<T: JsonSchema + DeserializeOwned>(
+    ctx: &Context,
+    meta: &CallMeta,
+    query: &str,
+) -> Result<(T, Vec<Citation>)> {
+    get_llm_structured_chat(ctx, meta, vec![LLMMsg::user(query)]).await
+}
+
+/// Structured answer to a conversation, see [`get_llm_structured`].
+/// The repair request continues the conversation after the unparsable answer.
+pub async fn get_llm_structured_chat<T: JsonSchema + DeserializeOwned>(
+    ctx: &Context,
+    meta: &CallMeta,
+    messages: Vec<LLMMsg>,
+) -> Result<(T, Vec<Citation>)> {
+    let req = chat_req(ctx, messages, Some(JsonSchemaSpec::of::<T>()));
+    structured_from_req(ctx, meta, req).await
+}
+
+/// one of several independent answers to the same conversation
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub model: String,
+    pub temperature: f64,
+    /// position among the samples, cached separately
+    pub index: usize,
+}
+
+/// [`get_llm_structured_chat`] with the model and temperature of a sample
+pub async fn get_llm_structured_sample<T: JsonSchema + DeserializeOwned>(
+    ctx: &Context,
+    meta: &CallMeta,
+    messages: Vec<LLMMsg>,
+    sample: &Sample,
+) -> Result<(T, Vec<Citation>)> {
+    let mut req = chat_req(ctx, messages, Some(JsonSchemaSpec::of::<T>()));
+    req.model = sample.model.clone();
+    req.temperature = sample.temperature;
+    req.sample = sample.index;
+    structured_from_req(ctx, meta, req).await
+}
+
+async fn structured_from_req<T: JsonSchema + DeserializeOwned>(
+    ctx: &Context,
+    meta: &CallMeta,
+    req: CompletionReq,
+) -> Result<(T, Vec<Citation>)> {
+    let ans = complete_cached(ctx, meta, &req).await?;
+    log::debug!("Structured Ans: {}", ans.content);
+    match parse_ans(&ans.content) {
+        Ok(val) => Ok((val, ans.citations)),
         Err(e) => {
-            panic!("Failed to construct client: {}", e);
+            log::warn!("Invalid json answer, send a repair request: {}", e);
+            let schema = req
+                .json_schema
+                .as_ref()
+                .map(|spec| spec.schema.to_string())
+                .unwrap_or_default();
+            let data = serde_json::json!({
+                "schema": schema,
+                "answer": ans.content,
+            });
+            let (repair_query, _) = ctx.prompts().render("repair", &data)?;
+            let mut req = req;
+            req.messages.push(LLMMsg::assistant(&ans.content));
+            req.messages.push(LLMMsg::user(&repair_query));
+            let repaired = complete_cached(ctx, meta, &req).await?;
+            Ok((parse_ans(&repaired.content)?, ans.citations))
         }
-    })
+    }
 }
-fn construct_llm_api_client() -> Result<Client> {
-    let mut headers = HeaderMap::new();
-    let api_key = get_api_key();
-    let auth_val = format!("Bearer {}", api_key);
-    headers.insert(AUTHORIZATION, HeaderValue::from_str(&auth_val)?);
-    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-
-    let client = ClientBuilder::new().default_headers(headers).build()?;
-    Ok(client)
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mock_llm::{Fault, MockRule, MockScript, MockServer},
+        test_util::mock_ctx,
+    };
+
+    #[tokio::test]
+    async fn test_completion_with_mock() -> Result<()> {
+        let script = MockScript::default()
+            .rule(MockRule::fault("^ping$", Fault::RateLimit).times(1))
+            .rule(MockRule::fault("^ping$", Fault::Timeout).times(1))
+            .rule(MockRule::reply("^ping$", "pong"))
+            .rule(MockRule::fault("^broken$", Fault::Malformed))
+            .rule(MockRule::reply(
+                "^answer$",
+                "Sure:\n```json\n{\"available\": true, \"site_url\": \"https://a.org/\",}\n```",
+            ));
+        let server = MockServer::start(script).await?;
+        let ctx = mock_ctx(&server, "/nonexistent", "retry = 3\nllm_time_out = 1")?;
+        let meta = CallMeta::new("-", Stage::Adhoc);
+
+        // a 429 and a timeout are retried
+        assert_eq!(get_llm_completion(&ctx, &meta, "ping").await?, "pong");
+        assert_eq!(server.prompts().len(), 3);
+        assert!(get_llm_completion(&ctx, &meta, "broken").await.is_err());
+
+        let ans = get_llm_completion(&ctx, &meta, "answer").await?;
+        let parsed: serde_json::Value = parse_ans(&ans)?;
+        assert_eq!(parsed["site_url"], "https://a.org/");
+        assert!(get_llm_completion(&ctx, &meta, "unscripted").await.is_err());
+        assert_eq!(ctx.usage().report().total.calls, 2);
+        Ok(())
+    }
 }
@@ -0,0 +1,152 @@
+use std::{collections::BTreeMap, path::Path};
+
+use color_eyre::eyre::Result;
+use eyre::WrapErr;
+use handlebars::Handlebars;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// file extension of templates in the prompts dir
+const TEMPLATE_EXT: &str = "hbs";
+
+/// templates shipped with the binary, `<name>.hbs` in the prompts dir replaces one
+const BUILTIN_TEMPLATES: [(&str, &str); 7] = [
+    ("discovery", include_str!("../../prompts/discovery.hbs")),
+    (
+        "discovery_system",
+        include_str!("../../prompts/discovery_system.hbs"),
+    ),
+    ("link_batch", include_str!("../../prompts/link_batch.hbs")),
+    (
+        "link_related",
+        include_str!("../../prompts/link_related.hbs"),
+    ),
+    ("source_link", include_str!("../../prompts/source_link.hbs")),
+    ("repair", include_str!("../../prompts/repair.hbs")),
+    (
+        "agent_system",
+        include_str!("../../prompts/agent_system.hbs"),
+    ),
+];
+
+/// Which template produced a prompt, recorded with results to compare prompt versions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PromptRef {
+    pub name: String,
+    /// first 16 hex digits of the sha256 of the template text
+    pub hash: String,
+    /// template file, none for a built-in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fpath: Option<String>,
+}
+
+/// Named prompt templates rendered in handlebars strict mode, a missing variable is an error.
+pub struct Prompts {
+    reg: Handlebars<'static>,
+    refs: BTreeMap<String, PromptRef>,
+}
+
+fn template_hash(text: &str) -> String {
+    let digest = Sha256::digest(text.as_bytes());
+    digest.iter().take(8).map(|b| format!("{b:02x}")).collect()
+}
+
+impl Prompts {
+    fn empty() -> Self {
+        let mut reg = Handlebars::new();
+        reg.set_strict_mode(true);
+        // prompts are plain text, urls must not be html escaped
+        reg.register_escape_fn(handlebars::no_escape);
+        Self {
+            reg,
+            refs: BTreeMap::new(),
+        }
+    }
+
+    fn register(&mut self, name: &str, text: &str, fpath: Option<String>) -> Result<()> {
+        self.reg
+            .register_template_string(name, text)
+            .wrap_err_with(|| format!("invalid prompt template {name}"))?;
+        let pref = PromptRef {
+            name: name.to_string(),
+            hash: template_hash(text),
+            fpath,
+        };
+        self.refs.insert(name.to_string(), pref);
+        Ok(())
+    }
+
+    pub fn builtin() -> Self {
+        let mut prompts = Self::empty();
+        for (name, text) in BUILTIN_TEMPLATES {
+            prompts
+                .register(name, text, None)
+                .expect("built-in prompt templates compile");
+        }
+        prompts
+    }
+
+    /// built-ins, each replaced by `<dir>/<name>.hbs` if present; a missing dir means built-ins only
+    pub fn load(dir: &Path) -> Result<Self> {
+        let mut prompts = Self::builtin();
+        if !dir.is_dir() {
+            return Ok(prompts);
+        }
+        for (name, _) in BUILTIN_TEMPLATES {
+            let fpath = dir.join(format!("{name}.{TEMPLATE_EXT}"));
+            if !fpath.is_file() {
+                continue;
+            }
+            let text = std::fs::read_to_string(&fpath)?;
+            let fpath = fpath.to_string_lossy().to_string();
+            log::debug!("prompt template {} loaded from {}", name, fpath);
+            prompts.register(name, &text, Some(fpath))?;
+        }
+        Ok(prompts)
+    }
+
+    /// render template `name`, along with a reference to the template used
+    pub fn render<T: Serialize>(&self, name: &str, data: &T) -> Result<(String, PromptRef)> {
+        let Some(pref) = self.refs.get(name) else {
+            eyre::bail!("unknown prompt template {name}");
+        };
+        let text = self
+            .reg
+            .render(name, data)
+            .wrap_err_with(|| format!("failed to render prompt template {name}"))?;
+        Ok((text, pref.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_prompt_override_and_strict() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path();
+        std::fs::write(
+            dir.join("source_link.hbs"),
+            "is {{url}} a tarball of {{comp_name}}?",
+        )?;
+        let prompts = Prompts::load(dir)?;
+
+        let data = json!({"url": "https://a.org/x-1.0.tar.gz?a=1&b=2", "comp_name": "x"});
+        let (text, pref) = prompts.render("source_link", &data)?;
+        assert_eq!(
+            text,
+            "is https://a.org/x-1.0.tar.gz?a=1&b=2 a tarball of x?"
+        );
+        assert!(pref.fpath.is_some());
+        let (text, builtin) = prompts.render("link_related", &data)?;
+        assert!(text.contains("component x?"));
+        assert_eq!(builtin.hash.len(), 16);
+        assert!(builtin.fpath.is_none());
+
+        // missing variables are rejected instead of rendering as empty
+        assert!(prompts.render("source_link", &json!({"url": "u"})).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+use async_trait::async_trait;
+use color_eyre::eyre::Result;
+use eyre::bail;
+use reqwest::{
+    Client,
+    header::{HeaderMap, HeaderName, HeaderValue},
+};
+use serde::{Deserialize, Serialize};
+
+use super::{Completion, CompletionReq, LlmProvider, build_client};
+use crate::{
+    llm_api::{
+        config::{ApiConfig, ProviderKind},
+        entities::{AllowedRole, LLMMsg},
+        rate_limit::RateLimiter,
+        usage::TokenUsage,
+    },
+    utils::{RetryPolicy, post_with_retry},
+};
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// request json spec of the messages api, system prompt is a top level field
+#[derive(Serialize)]
+struct MessagesReq<'a> {
+    model: &'a str,
+    max_tokens: usize,
+    temperature: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    messages: Vec<&'a LLMMsg>,
+}
+
+#[derive(Deserialize)]
+struct MessagesResp {
+    content: Vec<ContentBlock>,
+    #[serde(default)]
+    usage: Option<MessagesUsage>,
+}
+
+#[derive(Deserialize)]
+struct MessagesUsage {
+    input_tokens: u64,
+    output_tokens: u64,
+}
+
+#[derive(Deserialize)]
+struct ContentBlock {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    text: String,
+}
+
+/// Anthropic messages style api
+pub struct AnthropicProvider {
+    api: ApiConfig,
+    client: Client,
+    limiter: RateLimiter,
+}
+
+impl AnthropicProvider {
+    pub fn new(api: &ApiConfig) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static("x-api-key"),
+            HeaderValue::from_str(api.get_api_key())?,
+        );
+        headers.insert(
+            HeaderName::from_static("anthropic-version"),
+            HeaderValue::from_static(ANTHROPIC_VERSION),
+        );
+        Ok(Self {
+            api: api.clone(),
+            client: build_client(api, headers)?,
+            limiter: RateLimiter::new(api),
+        })
+    }
+}
+
+#[async_trait]
+impl LlmProvider for AnthropicProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Anthropic
+    }
+
+    fn supports_json_schema(&self) -> bool {
+        false
+    }
+
+    async fn complete(&self, req: &CompletionReq) -> Result<Completion> {
+        let (sys_msgs, msgs): (Vec<&LLMMsg>, Vec<&LLMMsg>) = req
+            .messages
+            .iter()
+            .partition(|msg| matches!(msg.role, AllowedRole::SYSTEM));
+        let system = if sys_msgs.is_empty() {
+            None
+        } else {
+            let sys: Vec<&str> = sys_msgs.iter().map(|msg| msg.content.as_str()).collect();
+            Some(sys.join("\n\n"))
+        };
+        let payload = MessagesReq {
+            model: &req.model,
+            max_tokens: self.api.get_max_tokens(),
+            temperature: req.temperature,
+            system,
+            messages: msgs,
+        };
+
+        let est_tokens = self.limiter.estimate(req);
+        let resp = post_with_retry(
+            &self.client,
+            self.api.get_api_url(),
+            &payload,
+            &RetryPolicy::from_api(&self.api),
+            &self.limiter,
+            est_tokens,
+        )
+        .await?;
+        let resp_json: MessagesResp = resp.json().await?;
+
+        let texts: Vec<&str> = resp_json
+            .content
+            .iter()
+            .filter(|blk| blk.kind == "text")
+            .map(|blk| blk.text.as_str())
+            .collect();
+        if texts.is_empty() {
+            bail!("No text content in anthropic response");
+        }
+        let usage = resp_json.usage.as_ref().map(|it| TokenUsage {
+            prompt_tokens: it.input_tokens,
+            completion_tokens: it.output_tokens,
+        });
+        self.limiter.settle(est_tokens, usage);
+        Ok(Completion {
+            content: texts.concat(),
+            usage,
+            citations: vec![],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::llm_api::provider::stub::{serve_once, stub_api_config};
+
+    #[tokio::test]
+    async fn test_anthropic_roundtrip() -> Result<()> {
+        let (url, req_rx) = serve_once(json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "content": [{"type": "text", "text": "yes"}],
+            "stop_reason": "end_turn",
+            "usage": {"input_tokens": 10, "output_tokens": 1}
+        }))
+        .await;
+        let provider = AnthropicProvider::new(&stub_api_config("anthropic", &url))?;
+        let req = CompletionReq {
+            model: "test-model".to_string(),
+            temperature: 0.1,
+            messages: vec![
+                LLMMsg {
+                    role: AllowedRole::SYSTEM,
+                    content: "be brief".to_string(),
+                },
+                LLMMsg {
+                    role: AllowedRole::USER,
+                    content: "hi".to_string(),
+                },
+            ],
+            json_schema: None,
+            sample: 0,
+        };
+        let ans = provider.complete(&req).await?;
+        assert_eq!(ans.content, "yes");
+        assert_eq!(ans.usage.map(|it| it.completion_tokens), Some(1));
+
+        let sent = req_rx.await?;
+        assert!(sent.head.contains("x-api-key: test-key"));
+        assert_eq!(sent.body["system"], "be brief");
+        assert_eq!(sent.body["max_tokens"], 256);
+        assert_eq!(sent.body["messages"].as_array().unwrap().len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use color_eyre::eyre::Result;
+use reqwest::{
+    Client, ClientBuilder,
+    header::{CONTENT_TYPE, HeaderMap, HeaderValue},
+};
+use schemars::{JsonSchema, schema_for};
+
+use super::{
+    config::{ApiConfig, ProviderKind},
+    entities::LLMMsg,
+    search::Citation,
+    usage::TokenUsage,
+};
+
+pub mod anthropic;
+pub mod ollama;
+pub mod openai;
+
+#[cfg(test)]
+pub(crate) mod stub;
+
+/// json schema the answer has to follow
+#[derive(Debug, Clone)]
+pub struct JsonSchemaSpec {
+    pub name: String,
+    pub schema: serde_json::Value,
+}
+
+impl JsonSchemaSpec {
+    pub fn of<T: JsonSchema>() -> Self {
+        Self {
+            name: T::schema_name().to_string(),
+            schema: schema_for!(T).to_value(),
+        }
+    }
+}
+
+/// provider independent chat request
+#[derive(Debug, Clone)]
+pub struct CompletionReq {
+    pub model: String,
+    pub temperature: f64,
+    pub messages: Vec<LLMMsg>,
+    /// structured output, ignored by providers without json schema support
+    pub json_schema: Option<JsonSchemaSpec>,
+    /// index of a repeated sample of the same request, only tells cache entries apart
+    pub sample: usize,
+}
+
+/// provider independent chat answer
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub content: String,
+    /// none if the api did not report token counts
+    pub usage: Option<TokenUsage>,
+    /// ranked search results of search models
+    pub citations: Vec<Citation>,
+}
+
+/// A chat-completion backend, selected by `api.provider`.
+#[async_trait]
+pub trait LlmProvider: Send + Sync {
+    fn kind(&self) -> ProviderKind;
+
+    /// whether [`CompletionReq::json_schema`] is enforced by the api
+    fn supports_json_schema(&self) -> bool;
+
+    async fn complete(&self, req: &CompletionReq) -> Result<Completion>;
+}
+
+/// json client with the given extra headers, requests time out after `api.llm_time_out` seconds
+fn build_client(api: &ApiConfig, mut headers: HeaderMap) -> Result<Client> {
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    let client = ClientBuilder::new()
+        .default_headers(headers)
+        .timeout(Duration::from_secs(api.get_llm_timeout()))
+        .build()?;
+    Ok(client)
+}
+
+pub fn construct_provider(api: &ApiConfig) -> Result<Arc<dyn LlmProvider>> {
+    let provider: Arc<dyn LlmProvider> = match api.get_provider() {
+        ProviderKind::OpenAI => Arc::new(openai::OpenAIProvider::new(api)?),
+        ProviderKind::Anthropic => Arc::new(anthropic::AnthropicProvider::new(api)?),
+        ProviderKind::Ollama => Arc::new(ollama::OllamaProvider::new(api)?),
+    };
+    Ok(provider)
+}
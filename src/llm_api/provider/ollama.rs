@@ -0,0 +1,159 @@
+use async_trait::async_trait;
+use color_eyre::eyre::Result;
+use reqwest::{
+    Client,
+    header::{AUTHORIZATION, HeaderMap, HeaderValue},
+};
+use serde::{Deserialize, Serialize};
+
+use super::{Completion, CompletionReq, LlmProvider, build_client};
+use crate::{
+    llm_api::{
+        config::{ApiConfig, ProviderKind},
+        entities::LLMMsg,
+        rate_limit::RateLimiter,
+        usage::TokenUsage,
+    },
+    utils::{RetryPolicy, post_with_retry},
+};
+
+/// request json spec of ollama `/api/chat`
+#[derive(Serialize)]
+struct ChatReq<'a> {
+    model: &'a str,
+    messages: &'a [LLMMsg],
+    stream: bool,
+    /// json schema of the answer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<&'a serde_json::Value>,
+    options: ChatOptions,
+}
+
+#[derive(Serialize)]
+struct ChatOptions {
+    temperature: f64,
+}
+
+#[derive(Deserialize)]
+struct ChatResp {
+    message: ChatMsg,
+    prompt_eval_count: Option<u64>,
+    eval_count: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ChatMsg {
+    content: String,
+}
+
+/// local ollama server, `api.key` is optional
+pub struct OllamaProvider {
+    api: ApiConfig,
+    client: Client,
+    limiter: RateLimiter,
+}
+
+impl OllamaProvider {
+    pub fn new(api: &ApiConfig) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        if !api.get_api_key().is_empty() {
+            let auth_val = format!("Bearer {}", api.get_api_key());
+            headers.insert(AUTHORIZATION, HeaderValue::from_str(&auth_val)?);
+        }
+        Ok(Self {
+            api: api.clone(),
+            client: build_client(api, headers)?,
+            limiter: RateLimiter::new(api),
+        })
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OllamaProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Ollama
+    }
+
+    fn supports_json_schema(&self) -> bool {
+        self.api.get_json_schema()
+    }
+
+    async fn complete(&self, req: &CompletionReq) -> Result<Completion> {
+        let payload = ChatReq {
+            model: &req.model,
+            messages: &req.messages,
+            stream: false,
+            format: req
+                .json_schema
+                .as_ref()
+                .filter(|_| self.supports_json_schema())
+                .map(|spec| &spec.schema),
+            options: ChatOptions {
+                temperature: req.temperature,
+            },
+        };
+        let est_tokens = self.limiter.estimate(req);
+        let resp = post_with_retry(
+            &self.client,
+            self.api.get_api_url(),
+            &payload,
+            &RetryPolicy::from_api(&self.api),
+            &self.limiter,
+            est_tokens,
+        )
+        .await?;
+        let resp_json: ChatResp = resp.json().await?;
+        let usage = match (resp_json.prompt_eval_count, resp_json.eval_count) {
+            (None, None) => None,
+            (prompt, eval) => Some(TokenUsage {
+                prompt_tokens: prompt.unwrap_or(0),
+                completion_tokens: eval.unwrap_or(0),
+            }),
+        };
+        self.limiter.settle(est_tokens, usage);
+        Ok(Completion {
+            content: resp_json.message.content,
+            usage,
+            citations: vec![],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::llm_api::{
+        entities::AllowedRole,
+        provider::stub::{serve_once, stub_api_config},
+    };
+
+    #[tokio::test]
+    async fn test_ollama_roundtrip() -> Result<()> {
+        let (url, req_rx) = serve_once(json!({
+            "model": "test-model",
+            "message": {"role": "assistant", "content": "yes"},
+            "done": true
+        }))
+        .await;
+        let provider = OllamaProvider::new(&stub_api_config("ollama", &url))?;
+        let req = CompletionReq {
+            model: "test-model".to_string(),
+            temperature: 0.1,
+            messages: vec![LLMMsg {
+                role: AllowedRole::USER,
+                content: "hi".to_string(),
+            }],
+            json_schema: None,
+            sample: 0,
+        };
+        let ans = provider.complete(&req).await?;
+        assert_eq!(ans.content, "yes");
+
+        let sent = req_rx.await?;
+        assert_eq!(sent.body["stream"], false);
+        assert_eq!(sent.body["options"]["temperature"], 0.1);
+        Ok(())
+    }
+}
@@ -0,0 +1,264 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use async_trait::async_trait;
+use color_eyre::eyre::Result;
+use reqwest::{
+    Client, Response,
+    header::{AUTHORIZATION, HeaderMap, HeaderValue},
+};
+use serde_json::json;
+
+use super::{Completion, CompletionReq, LlmProvider, build_client};
+use crate::{
+    llm_api::{
+        config::{ApiConfig, ProviderKind},
+        entities::{ReqBody, RespBody},
+        rate_limit::RateLimiter,
+        search::is_search_api,
+    },
+    utils::{RetryPolicy, post_with_retry},
+};
+
+/// OpenAI compatible chat-completions api, also served by llama.cpp and vLLM
+pub struct OpenAIProvider {
+    api: ApiConfig,
+    client: Client,
+    limiter: RateLimiter,
+    /// set once the server turned `response_format` down, later requests go without it
+    schema_rejected: AtomicBool,
+}
+
+/// a 400 complaining about `response_format`, sent by servers without structured output
+fn rejects_response_format(err: &eyre::Report) -> bool {
+    let msg = err.to_string();
+    msg.contains("status 400") && msg.contains("response_format")
+}
+
+impl OpenAIProvider {
+    pub fn new(api: &ApiConfig) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        let auth_val = format!("Bearer {}", api.get_api_key());
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(&auth_val)?);
+        Ok(Self {
+            api: api.clone(),
+            client: build_client(api, headers)?,
+            limiter: RateLimiter::new(api),
+            schema_rejected: AtomicBool::new(false),
+        })
+    }
+
+    async fn post(&self, payload: &ReqBody, est_tokens: u64) -> Result<Response> {
+        post_with_retry(
+            &self.client,
+            self.api.get_api_url(),
+            payload,
+            &RetryPolicy::from_api(&self.api),
+            &self.limiter,
+            est_tokens,
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OpenAIProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::OpenAI
+    }
+
+    fn supports_json_schema(&self) -> bool {
+        self.api.get_json_schema() && !self.schema_rejected.load(Ordering::Relaxed)
+    }
+
+    async fn complete(&self, req: &CompletionReq) -> Result<Completion> {
+        let mut payload = ReqBody {
+            model: req.model.clone(),
+            temperature: req.temperature,
+            messages: req.messages.clone(),
+            response_format: req
+                .json_schema
+                .as_ref()
+                .filter(|_| self.supports_json_schema())
+                .map(|spec| {
+                    json!({
+                        "type": "json_schema",
+                        "json_schema": {"name": spec.name, "schema": spec.schema, "strict": false}
+                    })
+                }),
+        };
+        let est_tokens = self.limiter.estimate(req);
+        // the tolerant answer parser takes over without structured output
+        let resp = match self.post(&payload, est_tokens).await {
+            Err(e) if payload.response_format.is_some() && rejects_response_format(&e) => {
+                log::warn!(
+                    "{} does not support response_format, retry without structured output",
+                    self.api.get_api_url()
+                );
+                self.schema_rejected.store(true, Ordering::Relaxed);
+                payload.response_format = None;
+                self.post(&payload, est_tokens).await?
+            }
+            res => res?,
+        };
+
+        let resp_json: RespBody = resp.json().await?;
+        let content = resp_json.get_content()?;
+        let usage = resp_json.get_usage();
+        self.limiter.settle(est_tokens, usage);
+        let citations = resp_json.get_citations();
+        if is_search_api(&self.api) {
+            log::debug!("{} citations in search answer", citations.len());
+        }
+        Ok(Completion {
+            content,
+            usage,
+            citations,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm_api::{
+        entities::{AllowedRole, LLMMsg},
+        provider::{
+            JsonSchemaSpec,
+            stub::{StubResp, serve_once, serve_seq, stub_api_config, stub_api_config_with},
+        },
+    };
+
+    #[tokio::test]
+    async fn test_openai_roundtrip() -> Result<()> {
+        let (url, req_rx) = serve_once(json!({
+            "id": "chatcmpl-1",
+            "model": "test-model",
+            "choices": [{
+                "index": 0,
+                "finish_reason": "stop",
+                "message": {"role": "assistant", "content": "yes"}
+            }],
+            "usage": {"prompt_tokens": 12, "completion_tokens": 3, "total_tokens": 15}
+        }))
+        .await;
+        let provider = OpenAIProvider::new(&stub_api_config("openai", &url))?;
+        let req = CompletionReq {
+            model: "test-model".to_string(),
+            temperature: 0.1,
+            messages: vec![LLMMsg {
+                role: AllowedRole::USER,
+                content: "hi".to_string(),
+            }],
+            json_schema: Some(JsonSchemaSpec {
+                name: "Ans".to_string(),
+                schema: json!({"type": "object"}),
+            }),
+            sample: 0,
+        };
+        let ans = provider.complete(&req).await?;
+        assert_eq!(ans.content, "yes");
+        assert_eq!(ans.usage.map(|it| it.prompt_tokens), Some(12));
+
+        let sent = req_rx.await?;
+        assert!(sent.head.contains("authorization: Bearer test-key"));
+        assert_eq!(sent.body["messages"][0]["role"], "user");
+        assert_eq!(sent.body["response_format"]["json_schema"]["name"], "Ans");
+        Ok(())
+    }
+
+    fn hi_req() -> CompletionReq {
+        CompletionReq {
+            model: "test-model".to_string(),
+            temperature: 0.1,
+            messages: vec![LLMMsg {
+                role: AllowedRole::USER,
+                content: "hi".to_string(),
+            }],
+            json_schema: None,
+            sample: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_openai_retry_by_status() -> Result<()> {
+        let ok_body = json!({
+            "id": "chatcmpl-1",
+            "model": "test-model",
+            "choices": [{
+                "index": 0,
+                "finish_reason": "stop",
+                "message": {"role": "assistant", "content": "yes"}
+            }]
+        });
+        let (url, mut req_rx) = serve_seq(vec![
+            StubResp {
+                status: 429,
+                headers: vec![("Retry-After", "0".to_string())],
+                body: json!({"error": "slow down"}),
+            },
+            StubResp::ok(ok_body),
+        ])
+        .await;
+        let api = stub_api_config_with("openai", &url, "retry = 3\nrpm = 600");
+        let ans = OpenAIProvider::new(&api)?.complete(&hi_req()).await?;
+        assert_eq!(ans.content, "yes");
+        assert!(req_rx.recv().await.is_some() && req_rx.recv().await.is_some());
+
+        // a client error is not retried and surfaces the body
+        let (url, _req_rx) = serve_seq(vec![StubResp {
+            status: 400,
+            headers: vec![],
+            body: json!({"error": "bad model"}),
+        }])
+        .await;
+        let api = stub_api_config_with("openai", &url, "retry = 3");
+        let err = OpenAIProvider::new(&api)?
+            .complete(&hi_req())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("bad model"), "{err}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_openai_without_response_format() -> Result<()> {
+        let ok_body = json!({
+            "id": "chatcmpl-1",
+            "model": "test-model",
+            "choices": [{
+                "index": 0,
+                "finish_reason": "stop",
+                "message": {"role": "assistant", "content": "{}"}
+            }]
+        });
+        let (url, mut req_rx) = serve_seq(vec![
+            StubResp {
+                status: 400,
+                headers: vec![],
+                body: json!({"error": "unknown field response_format"}),
+            },
+            StubResp::ok(ok_body.clone()),
+            StubResp::ok(ok_body),
+        ])
+        .await;
+        let provider = OpenAIProvider::new(&stub_api_config("openai", &url))?;
+        let req = CompletionReq {
+            json_schema: Some(JsonSchemaSpec {
+                name: "Ans".to_string(),
+                schema: json!({"type": "object"}),
+            }),
+            ..hi_req()
+        };
+        assert_eq!(provider.complete(&req).await?.content, "{}");
+        let first = req_rx.recv().await.expect("first request");
+        assert!(first.body.get("response_format").is_some());
+        let retried = req_rx.recv().await.expect("retried request");
+        assert!(retried.body.get("response_format").is_none());
+
+        // later requests skip it from the start
+        provider.complete(&req).await?;
+        let next = req_rx.recv().await.expect("next request");
+        assert!(next.body.get("response_format").is_none());
+        Ok(())
+    }
+}
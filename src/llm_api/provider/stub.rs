@@ -0,0 +1,127 @@
+//! Minimal one-shot http server standing in for an llm api in tests.
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, oneshot},
+};
+
+/// raw request received by the stub
+pub struct StubReq {
+    pub head: String,
+    pub body: serde_json::Value,
+}
+
+/// canned response of the stub
+pub struct StubResp {
+    pub status: u16,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: serde_json::Value,
+}
+
+impl StubResp {
+    pub fn ok(body: serde_json::Value) -> Self {
+        Self {
+            status: 200,
+            headers: vec![],
+            body,
+        }
+    }
+}
+
+/// Serve `resp_body` to a single request, return the url and the captured request.
+pub async fn serve_once(resp_body: serde_json::Value) -> (String, oneshot::Receiver<StubReq>) {
+    let (url, mut req_rx) = serve_seq(vec![StubResp::ok(resp_body)]).await;
+    let (tx, rx) = oneshot::channel();
+    tokio::spawn(async move {
+        if let Some(req) = req_rx.recv().await {
+            let _ = tx.send(req);
+        }
+    });
+    (url, rx)
+}
+
+/// read one request off the socket
+async fn read_req(sock: &mut TcpStream) -> StubReq {
+    let mut buf = vec![];
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = sock.read(&mut chunk).await.unwrap();
+        buf.extend_from_slice(&chunk[..n]);
+        let text = String::from_utf8_lossy(&buf).to_string();
+        if let Some((head, body)) = text.split_once("\r\n\r\n") {
+            let len = head
+                .lines()
+                .find_map(|l| {
+                    let (k, v) = l.split_once(':')?;
+                    k.eq_ignore_ascii_case("content-length")
+                        .then(|| v.trim().parse::<usize>().ok())?
+                })
+                .unwrap_or(0);
+            if body.len() >= len {
+                return StubReq {
+                    head: head.to_string(),
+                    body: serde_json::from_str(body).unwrap_or_default(),
+                };
+            }
+        }
+    }
+}
+
+/// Serve `resps` to consecutive requests, one connection each.
+pub async fn serve_seq(resps: Vec<StubResp>) -> (String, mpsc::UnboundedReceiver<StubReq>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/v1/chat", listener.local_addr().unwrap());
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        for resp in resps {
+            let (mut sock, _) = listener.accept().await.unwrap();
+            let req = read_req(&mut sock).await;
+            let body = resp.body.to_string();
+            let mut raw = format!(
+                "HTTP/1.1 {} STUB\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+                resp.status,
+                body.len()
+            );
+            for (k, v) in resp.headers.iter() {
+                raw.push_str(&format!("{k}: {v}\r\n"));
+            }
+            raw.push_str("\r\n");
+            raw.push_str(&body);
+            sock.write_all(raw.as_bytes()).await.unwrap();
+            let _ = tx.send(req);
+        }
+    });
+    (url, rx)
+}
+
+/// api config pointing at a stub server
+pub fn stub_api_config(provider: &str, api_url: &str) -> crate::llm_api::config::ApiConfig {
+    stub_api_config_with(provider, api_url, "retry = 1")
+}
+
+/// like [`stub_api_config`], `extra` is appended to the `[api]` table
+pub fn stub_api_config_with(
+    provider: &str,
+    api_url: &str,
+    extra: &str,
+) -> crate::llm_api::config::ApiConfig {
+    let toml_str = format!(
+        r#"
+[api]
+provider = "{provider}"
+key = "test-key"
+api_url = "{api_url}"
+model_id = "test-model"
+max_tokens = 256
+retry_delay = 0
+{extra}
+
+[download]
+username = "user"
+base_dir = "/nonexistent"
+"#
+    );
+    crate::config::load_config_from_str(&toml_str).unwrap().api
+}
@@ -0,0 +1,144 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::{config::ApiConfig, provider::CompletionReq, usage::TokenUsage};
+
+/// refills `capacity` units evenly over a minute
+struct Bucket {
+    capacity: f64,
+    avail: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn per_minute(capacity: u64) -> Option<Self> {
+        (capacity > 0).then(|| Self {
+            capacity: capacity as f64,
+            avail: capacity as f64,
+            last: Instant::now(),
+        })
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let secs = now.duration_since(self.last).as_secs_f64();
+        self.avail = (self.avail + secs * self.capacity / 60.0).min(self.capacity);
+        self.last = now;
+    }
+
+    /// time until `need` units are available, a need above capacity waits for a full bucket
+    fn wait_for(&self, need: f64) -> Duration {
+        let need = need.min(self.capacity);
+        if self.avail >= need {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((need - self.avail) * 60.0 / self.capacity)
+    }
+}
+
+/// Requests-per-minute and tokens-per-minute limits of `[api]`, shared by all calls of a provider.
+/// A limit of 0 disables the bucket.
+pub struct RateLimiter {
+    max_tokens: u64,
+    buckets: Mutex<(Option<Bucket>, Option<Bucket>)>,
+}
+
+impl RateLimiter {
+    pub fn new(api: &ApiConfig) -> Self {
+        Self {
+            max_tokens: api.get_max_tokens() as u64,
+            buckets: Mutex::new((
+                Bucket::per_minute(api.get_rpm()),
+                Bucket::per_minute(api.get_tpm()),
+            )),
+        }
+    }
+
+    /// rough token count of a request: prompt chars / 4 plus the completion budget
+    pub fn estimate(&self, req: &CompletionReq) -> u64 {
+        let chars: usize = req.messages.iter().map(|msg| msg.content.len()).sum();
+        chars as u64 / 4 + self.max_tokens
+    }
+
+    /// wait until one request of about `tokens` tokens fits into both buckets, then take it
+    pub async fn acquire(&self, tokens: u64) {
+        loop {
+            let wait = {
+                let mut guard = self.buckets.lock().expect("rate limiter poisoned");
+                let (rpm, tpm) = &mut *guard;
+                let now = Instant::now();
+                let mut wait = Duration::ZERO;
+                if let Some(bkt) = rpm.as_mut() {
+                    bkt.refill(now);
+                    wait = wait.max(bkt.wait_for(1.0));
+                }
+                if let Some(bkt) = tpm.as_mut() {
+                    bkt.refill(now);
+                    wait = wait.max(bkt.wait_for(tokens as f64));
+                }
+                if wait.is_zero() {
+                    if let Some(bkt) = rpm.as_mut() {
+                        bkt.avail -= 1.0;
+                    }
+                    if let Some(bkt) = tpm.as_mut() {
+                        bkt.avail -= (tokens as f64).min(bkt.capacity);
+                    }
+                    return;
+                }
+                wait
+            };
+            log::debug!("rate limited, wait {:?}", wait);
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Correct the token bucket once the api reported what a request really used.
+    /// `reserved` is the estimate passed to [`RateLimiter::acquire`], which took at most the capacity.
+    pub fn settle(&self, reserved: u64, usage: Option<TokenUsage>) {
+        let Some(usage) = usage else {
+            return;
+        };
+        let used = usage.prompt_tokens + usage.completion_tokens;
+        let mut guard = self.buckets.lock().expect("rate limiter poisoned");
+        if let Some(bkt) = guard.1.as_mut() {
+            let reserved = (reserved as f64).min(bkt.capacity);
+            bkt.avail = (bkt.avail + reserved - used as f64).min(bkt.capacity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_wait() {
+        let mut bkt = Bucket::per_minute(60).unwrap();
+        assert_eq!(bkt.wait_for(60.0), Duration::ZERO);
+        bkt.avail = 0.0;
+        assert_eq!(bkt.wait_for(1.0), Duration::from_secs(1));
+        // more than a minute's worth only waits for a full bucket
+        assert_eq!(bkt.wait_for(120.0), Duration::from_secs(60));
+        assert!(Bucket::per_minute(0).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_settle_above_capacity() {
+        let limiter = RateLimiter {
+            max_tokens: 0,
+            buckets: Mutex::new((None, Bucket::per_minute(100))),
+        };
+        limiter.acquire(500).await;
+        limiter.settle(
+            500,
+            Some(TokenUsage {
+                prompt_tokens: 40,
+                completion_tokens: 10,
+            }),
+        );
+        let avail = limiter.buckets.lock().unwrap().1.as_ref().unwrap().avail;
+        // only the 100 taken are given back, the 50 used stay spent
+        assert!((50.0..51.0).contains(&avail), "{avail}");
+    }
+}
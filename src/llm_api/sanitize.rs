@@ -0,0 +1,136 @@
+use color_eyre::eyre::Result;
+use eyre::bail;
+use regex::Regex;
+use serde::de::DeserializeOwned;
+
+/// Snippets of an llm answer that may hold the json payload, most likely first:
+/// fenced blocks with any or no language tag, the whole answer, then `{...}` spans in prose.
+fn json_candidates(ans: &str) -> Result<Vec<String>> {
+    let re = Regex::new(r"(?s)```[[:alpha:]]*[ \t]*\r?\n?(.*?)```")?;
+    let mut cands: Vec<String> = re
+        .captures_iter(ans)
+        .map(|caps| caps[1].trim().to_string())
+        .collect();
+    cands.push(ans.trim().to_string());
+    cands.extend(brace_spans(ans).into_iter().map(str::to_string));
+    Ok(cands)
+}
+
+/// top level balanced `{...}` spans, braces inside strings are ignored
+fn brace_spans(text: &str) -> Vec<&str> {
+    let mut spans = vec![];
+    let mut depth = 0usize;
+    let mut start = 0;
+    let mut in_str = false;
+    let mut escaped = false;
+    for (idx, ch) in text.char_indices() {
+        if in_str {
+            match ch {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_str = false,
+                _ => {}
+            }
+            continue;
+        }
+        match ch {
+            '"' if depth > 0 => in_str = true,
+            '{' => {
+                if depth == 0 {
+                    start = idx;
+                }
+                depth += 1;
+            }
+            '}' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    spans.push(&text[start..=idx]);
+                }
+            }
+            _ => {}
+        }
+    }
+    spans
+}
+
+/// drop commas right before `}` or `]`, outside of strings
+fn strip_trailing_commas(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut res = String::with_capacity(text.len());
+    let mut in_str = false;
+    let mut escaped = false;
+    for (idx, &ch) in chars.iter().enumerate() {
+        if in_str {
+            match ch {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_str = false,
+                _ => {}
+            }
+        } else if ch == '"' {
+            in_str = true;
+        } else if ch == ',' {
+            let next = chars[idx + 1..].iter().find(|c| !c.is_whitespace());
+            if matches!(next, Some('}') | Some(']')) {
+                continue;
+            }
+        }
+        res.push(ch);
+    }
+    res
+}
+
+/// candidate as is, then with trailing commas removed
+fn parse_candidate<T: DeserializeOwned>(cand: &str) -> Option<T> {
+    if let Ok(val) = serde_json::from_str(cand) {
+        return Some(val);
+    }
+    serde_json::from_str(&strip_trailing_commas(cand)).ok()
+}
+
+/// Parse the first json snippet of an llm answer that fits `T`.
+pub fn parse_ans<T: DeserializeOwned>(ans: &str) -> Result<T> {
+    for cand in json_candidates(ans)? {
+        if let Some(val) = parse_candidate::<T>(&cand) {
+            return Ok(val);
+        }
+    }
+    bail!("No valid json found in the answer: {}", ans);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct Ans {
+        available: bool,
+        site_url: Option<String>,
+    }
+
+    #[test]
+    fn test_tolerant_parse() -> Result<()> {
+        let cases = [
+            r#"{"available": true, "site_url": "https://a.org/"}"#,
+            "```JSON\n{\"available\": true, \"site_url\": \"https://a.org/\"}\n```",
+            "Sure! Here it is: {\"available\": true, \"site_url\": \"https://a.org/\",} Hope it helps.",
+            "```json\n{\"example\": 1}\n```\nand the answer:\n```json\n{\"available\": true, \"site_url\": \"https://a.org/`x`\"}\n```",
+        ];
+        for ans in cases {
+            let parsed: Ans = parse_ans(ans)?;
+            assert!(parsed.available, "{ans}");
+            assert!(parsed.site_url.unwrap().starts_with("https://a.org/"));
+        }
+        assert!(parse_ans::<Ans>("no json here").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_strip_trailing_commas() {
+        assert_eq!(
+            strip_trailing_commas(r#"{"a": [1, 2, ], "b": ",}",}"#),
+            r#"{"a": [1, 2 ], "b": ",}"}"#
+        );
+    }
+}
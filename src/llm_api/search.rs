@@ -1,15 +1,158 @@
-use color_eyre::eyre::Result;
-use reqwest::Response;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-use super::config::get_model_id;
+use super::config::ApiConfig;
 
-pub fn is_search_api() -> bool {
-    let model_id = get_model_id();
-    model_id.contains("search")
+pub fn is_search_api(api: &ApiConfig) -> bool {
+    api.get_model_id().contains("search")
 }
 
-pub async fn show_search_resp_content(resp: Response) -> Result<()> {
-    let text = resp.text().await?;
-    log::debug!("Raw Text: {}", text);
-    Ok(())
+/// `bot_usage.action_details[]`, one per plugin the bot invoked
+#[derive(Debug, Deserialize)]
+pub struct ActionDetail {
+    pub name: String,
+    #[serde(default)]
+    pub tool_details: Vec<ToolDetail>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ToolDetail {
+    pub name: String,
+    /// kept raw, only outputs of the search tool are parsed
+    #[serde(default)]
+    pub output: Option<Value>,
+}
+
+/// `output` of a search tool call
+#[derive(Debug, Deserialize)]
+struct SearchOutput {
+    data: SearchOutputData,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchOutputData {
+    /// http style, 200 on success
+    #[serde(default)]
+    status_code: Option<i64>,
+    data: SearchResults,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchResults {
+    #[serde(default)]
+    results: Vec<SearchResult>,
+}
+
+/// a single search engine hit
+#[derive(Debug, Deserialize)]
+pub struct SearchResult {
+    pub url: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub site_name: String,
+    #[serde(default)]
+    pub summary: String,
+    #[serde(default)]
+    pub search_plugin_data: Option<SearchScores>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchScores {
+    #[serde(default)]
+    pub auth_score: Option<f64>,
+    #[serde(default)]
+    pub rel_score: Option<f64>,
+}
+
+/// top level `references`, the hits the answer actually cites
+#[derive(Debug, Deserialize)]
+pub struct Reference {
+    pub url: String,
+}
+
+impl ToolDetail {
+    /// hits of a search tool call, empty for other tools or failed searches
+    pub fn search_results(&self) -> Vec<SearchResult> {
+        if self.name != "search" {
+            return vec![];
+        }
+        let Some(output) = self.output.clone() else {
+            return vec![];
+        };
+        match serde_json::from_value::<SearchOutput>(output) {
+            Ok(out) if (200..300).contains(&out.data.status_code.unwrap_or(200)) => {
+                out.data.data.results
+            }
+            Ok(out) => {
+                log::warn!("search tool failed with status {:?}", out.data.status_code);
+                vec![]
+            }
+            Err(e) => {
+                log::warn!("Unexpected search tool output: {}", e);
+                vec![]
+            }
+        }
+    }
+}
+
+/// a url the search model looked at, ranked by [`rank_citations`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Citation {
+    pub url: String,
+    pub title: String,
+    pub site_name: String,
+    /// relevance score of the search engine
+    pub rel_score: Option<f64>,
+    pub auth_score: Option<f64>,
+    /// listed in the `references` of the answer
+    pub cited: bool,
+}
+
+/// Deduplicated hits of all search calls: cited ones first, then by relevance.
+pub fn rank_citations(actions: &[ActionDetail], refs: &[Reference]) -> Vec<Citation> {
+    let mut cites: Vec<Citation> = vec![];
+    let results = actions
+        .iter()
+        .flat_map(|act| act.tool_details.iter())
+        .flat_map(|tool| tool.search_results());
+    for res in results {
+        if cites.iter().any(|it| it.url == res.url) {
+            continue;
+        }
+        let scores = res.search_plugin_data.as_ref();
+        cites.push(Citation {
+            cited: refs.iter().any(|it| it.url == res.url),
+            url: res.url,
+            title: res.title,
+            site_name: res.site_name,
+            rel_score: scores.and_then(|it| it.rel_score),
+            auth_score: scores.and_then(|it| it.auth_score),
+        });
+    }
+    cites.sort_by(|a, b| {
+        b.cited.cmp(&a.cited).then(
+            b.rel_score
+                .unwrap_or(-1.0)
+                .total_cmp(&a.rel_score.unwrap_or(-1.0)),
+        )
+    });
+    cites
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::llm_api::entities::RespBody;
+    use color_eyre::eyre::Result;
+
+    #[test]
+    fn test_rank_citations() -> Result<()> {
+        let resp: RespBody = serde_json::from_str(include_str!("../../resp.json"))?;
+        let cites = resp.get_citations();
+        assert_eq!(cites.len(), 10);
+        assert_eq!(cites.iter().filter(|it| it.cited).count(), 8);
+        assert!(cites[..8].iter().all(|it| it.cited));
+        assert!(cites[0].rel_score >= cites[1].rel_score);
+        Ok(())
+    }
 }
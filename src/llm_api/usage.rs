@@ -0,0 +1,244 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::AddAssign,
+    path::Path,
+    sync::Mutex,
+};
+
+use color_eyre::eyre::Result;
+use serde::{Deserialize, Serialize};
+
+/// pipeline stage an llm call is made for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// asking for the download page of a component
+    Discovery,
+    /// whether a link on the page belongs to the component
+    RelatednessCheck,
+    /// whether a link points to a source archive
+    SourceCheck,
+    /// batched source and relatedness verdicts for a chunk of links
+    LinkClassification,
+    /// one-off prompts, e.g. `llm ask`
+    Adhoc,
+}
+
+/// who an llm call is billed to
+#[derive(Debug, Clone)]
+pub struct CallMeta {
+    pub component: String,
+    pub stage: Stage,
+}
+
+impl CallMeta {
+    pub fn new(component: &str, stage: Stage) -> Self {
+        Self {
+            component: component.to_string(),
+            stage,
+        }
+    }
+}
+
+/// token counts reported by the api for a single answer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl AddAssign for TokenUsage {
+    fn add_assign(&mut self, rhs: Self) {
+        self.prompt_tokens += rhs.prompt_tokens;
+        self.completion_tokens += rhs.completion_tokens;
+    }
+}
+
+/// usd per million tokens, `[api.prices."<model>"]`
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct ModelPrice {
+    #[serde(default)]
+    pub prompt: f64,
+    #[serde(default)]
+    pub completion: f64,
+}
+
+impl ModelPrice {
+    fn cost(&self, tokens: &TokenUsage) -> f64 {
+        (tokens.prompt_tokens as f64 * self.prompt
+            + tokens.completion_tokens as f64 * self.completion)
+            / 1_000_000.0
+    }
+}
+
+/// aggregated calls and tokens
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UsageStat {
+    pub calls: u64,
+    /// answered from the llm cache, no tokens spent
+    pub cached_calls: u64,
+    /// answers without a usage block from the api
+    pub unreported_calls: u64,
+    #[serde(flatten)]
+    pub tokens: TokenUsage,
+    /// estimated usd, none when no price is configured for a model involved
+    pub cost: Option<f64>,
+}
+
+impl UsageStat {
+    fn merge(&mut self, other: &UsageStat) {
+        self.calls += other.calls;
+        self.cached_calls += other.cached_calls;
+        self.unreported_calls += other.unreported_calls;
+        self.tokens += other.tokens;
+        self.cost = match (self.cost, other.cost) {
+            (Some(a), Some(b)) => Some(a + b),
+            _ => None,
+        };
+    }
+}
+
+/// Content of `usage.json`, totals first, then per stage, model and component.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UsageReport {
+    pub total: UsageStat,
+    pub by_stage: BTreeMap<Stage, UsageStat>,
+    pub by_model: BTreeMap<String, UsageStat>,
+    pub by_component: BTreeMap<String, BTreeMap<Stage, UsageStat>>,
+}
+
+impl UsageReport {
+    pub fn save_to(&self, fpath: &Path) -> Result<()> {
+        let json_str = serde_json::to_string_pretty(self)?;
+        std::fs::write(fpath, json_str)?;
+        Ok(())
+    }
+}
+
+type LedgerKey = (String, Stage, String);
+
+/// Token usage of every llm call of a run, shared by all workers of a [`crate::context::Context`].
+pub struct UsageLedger {
+    prices: HashMap<String, ModelPrice>,
+    records: Mutex<BTreeMap<LedgerKey, UsageStat>>,
+}
+
+impl UsageLedger {
+    pub fn new(prices: HashMap<String, ModelPrice>) -> Self {
+        Self {
+            prices,
+            records: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn with_record(&self, meta: &CallMeta, model: &str, f: impl FnOnce(&mut UsageStat)) {
+        let key = (meta.component.clone(), meta.stage, model.to_string());
+        let mut records = self.records.lock().expect("usage ledger poisoned");
+        f(records.entry(key).or_default());
+    }
+
+    /// an answer from the api, `usage` is none if the api did not report it
+    pub fn record(&self, meta: &CallMeta, model: &str, usage: Option<TokenUsage>) {
+        self.with_record(meta, model, |stat| {
+            stat.calls += 1;
+            match usage {
+                Some(tokens) => stat.tokens += tokens,
+                None => stat.unreported_calls += 1,
+            }
+        });
+    }
+
+    /// an answer served from the llm cache
+    pub fn record_cached(&self, meta: &CallMeta, model: &str) {
+        self.with_record(meta, model, |stat| {
+            stat.calls += 1;
+            stat.cached_calls += 1;
+        });
+    }
+
+    pub fn report(&self) -> UsageReport {
+        let records = self.records.lock().expect("usage ledger poisoned");
+        let mut report = UsageReport {
+            total: UsageStat {
+                cost: Some(0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        for ((comp, stage, model), stat) in records.iter() {
+            let mut stat = stat.clone();
+            stat.cost = self.prices.get(model).map(|price| price.cost(&stat.tokens));
+            let empty = || UsageStat {
+                cost: Some(0.0),
+                ..Default::default()
+            };
+            report.total.merge(&stat);
+            report
+                .by_stage
+                .entry(*stage)
+                .or_insert_with(empty)
+                .merge(&stat);
+            report
+                .by_model
+                .entry(model.clone())
+                .or_insert_with(empty)
+                .merge(&stat);
+            report
+                .by_component
+                .entry(comp.clone())
+                .or_default()
+                .entry(*stage)
+                .or_insert_with(empty)
+                .merge(&stat);
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_report() {
+        let prices = HashMap::from([(
+            "model-a".to_string(),
+            ModelPrice {
+                prompt: 1.0,
+                completion: 2.0,
+            },
+        )]);
+        let ledger = UsageLedger::new(prices);
+        let tokens = TokenUsage {
+            prompt_tokens: 1_000_000,
+            completion_tokens: 500_000,
+        };
+        let discovery = CallMeta::new("coreutils", Stage::Discovery);
+        ledger.record(&discovery, "model-a", Some(tokens));
+        ledger.record_cached(&discovery, "model-a");
+        ledger.record(
+            &CallMeta::new("coreutils", Stage::SourceCheck),
+            "model-a",
+            None,
+        );
+
+        let report = ledger.report();
+        assert_eq!(report.total.calls, 3);
+        assert_eq!(report.total.cached_calls, 1);
+        assert_eq!(report.total.unreported_calls, 1);
+        assert_eq!(report.total.cost, Some(2.0));
+        assert_eq!(report.by_component["coreutils"][&Stage::Discovery].calls, 2);
+
+        ledger.record(
+            &CallMeta::new("bash", Stage::Discovery),
+            "model-b",
+            Some(tokens),
+        );
+        let report = ledger.report();
+        assert_eq!(
+            report.by_stage[&Stage::Discovery].tokens.prompt_tokens,
+            2_000_000
+        );
+        assert_eq!(report.total.cost, None);
+    }
+}
@@ -0,0 +1,460 @@
+//! Scripted OpenAI compatible chat-completions server on localhost, for offline runs and tests.
+//!
+//! Replies are picked by the first rule whose regex matches the last user message.
+//! The same server hosts static pages, so download pages and archives can be served as well.
+//! `{{base_url}}` in replies and page bodies is replaced by the url of the server.
+
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use color_eyre::eyre::Result;
+use eyre::WrapErr;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// placeholder for the url of the server
+const BASE_URL_VAR: &str = "{{base_url}}";
+
+/// failure injected in place of a reply
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fault {
+    /// 429 with `Retry-After: 0`
+    RateLimit,
+    /// 503 without a body
+    Unavailable,
+    /// no response until `delay_secs` passed, then the connection is dropped
+    Timeout,
+    /// 200 with a truncated json body
+    Malformed,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MockRule {
+    /// regex matched against the last user message
+    pub pattern: String,
+    #[serde(default)]
+    pub reply: String,
+    #[serde(default)]
+    pub fault: Option<Fault>,
+    /// how often the rule fires, 0 for no limit
+    #[serde(default)]
+    pub times: usize,
+    /// seconds a timeout fault holds the connection
+    #[serde(default = "default_delay_secs")]
+    pub delay_secs: u64,
+}
+
+/// just past the default `api.llm_time_out` of 120 seconds, so a client with a longer
+/// timeout still gets the connection dropped soon after
+fn default_delay_secs() -> u64 {
+    125
+}
+
+impl MockRule {
+    pub fn reply(pattern: &str, reply: &str) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+            reply: reply.to_string(),
+            fault: None,
+            times: 0,
+            delay_secs: default_delay_secs(),
+        }
+    }
+
+    pub fn fault(pattern: &str, fault: Fault) -> Self {
+        Self {
+            fault: Some(fault),
+            ..Self::reply(pattern, "")
+        }
+    }
+
+    pub fn times(mut self, times: usize) -> Self {
+        self.times = times;
+        self
+    }
+
+    pub fn delay_secs(mut self, secs: u64) -> Self {
+        self.delay_secs = secs;
+        self
+    }
+}
+
+/// static page served for GET and HEAD
+#[derive(Debug, Clone, Deserialize)]
+pub struct MockPage {
+    pub path: String,
+    #[serde(default)]
+    pub body: String,
+    #[serde(default = "default_content_type")]
+    pub content_type: String,
+    /// answer HEAD with 405, like servers that only implement GET
+    #[serde(default)]
+    pub no_head: bool,
+    /// answer with a 302 to this path or url instead of the body
+    #[serde(default)]
+    pub redirect: Option<String>,
+}
+
+fn default_content_type() -> String {
+    "text/html".to_string()
+}
+
+impl MockPage {
+    pub fn html(path: &str, body: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            body: body.to_string(),
+            content_type: default_content_type(),
+            no_head: false,
+            redirect: None,
+        }
+    }
+
+    pub fn redirect(path: &str, to: &str) -> Self {
+        Self {
+            redirect: Some(to.to_string()),
+            ..Self::html(path, "")
+        }
+    }
+
+    pub fn no_head(mut self) -> Self {
+        self.no_head = true;
+        self
+    }
+
+    pub fn archive(path: &str, body: &str) -> Self {
+        Self {
+            content_type: "application/gzip".to_string(),
+            ..Self::html(path, body)
+        }
+    }
+}
+
+/// rules and pages of a mock server, tried in order
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MockScript {
+    #[serde(default)]
+    pub rules: Vec<MockRule>,
+    #[serde(default)]
+    pub pages: Vec<MockPage>,
+}
+
+impl MockScript {
+    pub fn load(fpath: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(fpath)?;
+        toml::from_str(&text).wrap_err_with(|| format!("invalid mock script {fpath:?}"))
+    }
+
+    pub fn rule(mut self, rule: MockRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    pub fn page(mut self, page: MockPage) -> Self {
+        self.pages.push(page);
+        self
+    }
+}
+
+struct CompiledRule {
+    re: Regex,
+    rule: MockRule,
+    /// remaining firings, none for no limit
+    left: Mutex<Option<usize>>,
+}
+
+impl CompiledRule {
+    /// take one firing if the rule matches
+    fn fire(&self, prompt: &str) -> bool {
+        if !self.re.is_match(prompt) {
+            return false;
+        }
+        let mut left = self.left.lock().expect("mock rule poisoned");
+        match left.as_mut() {
+            None => true,
+            Some(0) => false,
+            Some(n) => {
+                *n -= 1;
+                true
+            }
+        }
+    }
+}
+
+struct State {
+    base_url: String,
+    rules: Vec<CompiledRule>,
+    pages: Vec<MockPage>,
+    /// last user message of every chat request, in arrival order
+    prompts: Mutex<Vec<String>>,
+}
+
+/// Running mock server, stopped when dropped.
+pub struct MockServer {
+    state: Arc<State>,
+    hdl: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.hdl.abort();
+    }
+}
+
+impl MockServer {
+    /// serve on a free port of 127.0.0.1
+    pub async fn start(script: MockScript) -> Result<Self> {
+        Self::bind("127.0.0.1:0", script).await
+    }
+
+    pub async fn bind(addr: &str, script: MockScript) -> Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let base_url = format!("http://{}", listener.local_addr()?);
+        let mut rules = vec![];
+        for rule in script.rules {
+            let re = Regex::new(&rule.pattern)
+                .wrap_err_with(|| format!("invalid mock rule pattern {}", rule.pattern))?;
+            rules.push(CompiledRule {
+                re,
+                left: Mutex::new((rule.times > 0).then_some(rule.times)),
+                rule,
+            });
+        }
+        let state = Arc::new(State {
+            base_url,
+            rules,
+            pages: script.pages,
+            prompts: Mutex::new(vec![]),
+        });
+        let st = state.clone();
+        let hdl = tokio::spawn(async move {
+            while let Ok((sock, _)) = listener.accept().await {
+                let st = st.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_conn(&st, sock).await {
+                        log::debug!("mock connection failed: {}", e);
+                    }
+                });
+            }
+        });
+        Ok(Self { state, hdl })
+    }
+
+    /// `http://127.0.0.1:<port>`, prefix of every page path
+    pub fn base_url(&self) -> &str {
+        &self.state.base_url
+    }
+
+    /// url for `api.api_url`, any POST is taken as a chat completion
+    pub fn chat_url(&self) -> String {
+        format!("{}/v1/chat/completions", self.state.base_url)
+    }
+
+    /// prompts received so far
+    pub fn prompts(&self) -> Vec<String> {
+        self.state
+            .prompts
+            .lock()
+            .expect("mock state poisoned")
+            .clone()
+    }
+}
+
+struct RawReq {
+    method: String,
+    path: String,
+    body: String,
+}
+
+async fn read_req(sock: &mut TcpStream) -> Result<RawReq> {
+    let mut buf = vec![];
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = sock.read(&mut chunk).await?;
+        if n == 0 {
+            eyre::bail!("connection closed before the request was complete");
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+            continue;
+        };
+        let head = String::from_utf8_lossy(&buf[..pos]).to_string();
+        let len = head
+            .lines()
+            .find_map(|l| {
+                let (k, v) = l.split_once(':')?;
+                k.eq_ignore_ascii_case("content-length")
+                    .then(|| v.trim().parse::<usize>().ok())?
+            })
+            .unwrap_or(0);
+        if buf.len() - pos - 4 < len {
+            continue;
+        }
+        let mut parts = head.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let path = parts.next().unwrap_or("/").to_string();
+        let body = String::from_utf8_lossy(&buf[pos + 4..pos + 4 + len]).to_string();
+        return Ok(RawReq { method, path, body });
+    }
+}
+
+struct RawResp {
+    status: u16,
+    content_type: String,
+    headers: Vec<(&'static str, String)>,
+    body: String,
+}
+
+impl RawResp {
+    fn json(status: u16, body: Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json".to_string(),
+            headers: vec![],
+            body: body.to_string(),
+        }
+    }
+}
+
+async fn write_resp(sock: &mut TcpStream, resp: &RawResp, with_body: bool) -> Result<()> {
+    let mut raw = format!(
+        "HTTP/1.1 {} MOCK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        resp.status,
+        resp.content_type,
+        resp.body.len()
+    );
+    for (k, v) in resp.headers.iter() {
+        raw.push_str(&format!("{k}: {v}\r\n"));
+    }
+    raw.push_str("\r\n");
+    if with_body {
+        raw.push_str(&resp.body);
+    }
+    sock.write_all(raw.as_bytes()).await?;
+    sock.flush().await?;
+    Ok(())
+}
+
+async fn serve_conn(st: &State, mut sock: TcpStream) -> Result<()> {
+    let req = read_req(&mut sock).await?;
+    let resp = match req.method.as_str() {
+        "POST" => match chat_resp(st, &req.body).await {
+            Some(resp) => resp,
+            // timeout fault, drop the connection without an answer
+            None => return Ok(()),
+        },
+        method => page_resp(st, method, &req.path),
+    };
+    write_resp(&mut sock, &resp, req.method != "HEAD").await
+}
+
+fn page_resp(st: &State, method: &str, path: &str) -> RawResp {
+    match st.pages.iter().find(|page| page.path == path) {
+        Some(page) if page.no_head && method == "HEAD" => RawResp {
+            status: 405,
+            content_type: "text/plain".to_string(),
+            headers: vec![("Allow", "GET".to_string())],
+            body: String::new(),
+        },
+        Some(MockPage {
+            redirect: Some(to), ..
+        }) => RawResp {
+            status: 302,
+            content_type: "text/plain".to_string(),
+            headers: vec![("Location", to.replace(BASE_URL_VAR, &st.base_url))],
+            body: String::new(),
+        },
+        Some(page) => RawResp {
+            status: 200,
+            content_type: page.content_type.clone(),
+            headers: vec![],
+            body: page.body.replace(BASE_URL_VAR, &st.base_url),
+        },
+        None => RawResp {
+            status: 404,
+            content_type: "text/plain".to_string(),
+            headers: vec![],
+            body: format!("no mock page at {path}"),
+        },
+    }
+}
+
+/// content of the last user message of a chat request
+fn last_user_msg(body: &Value) -> String {
+    let msgs = body["messages"].as_array().cloned().unwrap_or_default();
+    msgs.iter()
+        .rev()
+        .find(|msg| msg["role"] == "user")
+        .and_then(|msg| msg["content"].as_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// none if the request should time out
+async fn chat_resp(st: &State, body: &str) -> Option<RawResp> {
+    let body: Value = serde_json::from_str(body).unwrap_or_default();
+    let prompt = last_user_msg(&body);
+    st.prompts
+        .lock()
+        .expect("mock state poisoned")
+        .push(prompt.clone());
+    let Some(rule) = st
+        .rules
+        .iter()
+        .find(|it| it.fire(&prompt))
+        .map(|it| &it.rule)
+    else {
+        log::warn!("no mock rule matches prompt: {}", prompt);
+        let err = json!({"error": {"message": "no mock rule matches the prompt"}});
+        return Some(RawResp::json(400, err));
+    };
+    let resp = match rule.fault {
+        Some(Fault::RateLimit) => {
+            let mut resp = RawResp::json(429, json!({"error": {"message": "rate limited"}}));
+            resp.headers.push(("Retry-After", "0".to_string()));
+            resp
+        }
+        Some(Fault::Unavailable) => RawResp::json(503, json!({})),
+        Some(Fault::Timeout) => {
+            tokio::time::sleep(Duration::from_secs(rule.delay_secs)).await;
+            return None;
+        }
+        Some(Fault::Malformed) => RawResp {
+            body: r#"{"id": "mock", "choices": [{"message": {"#.to_string(),
+            ..RawResp::json(200, json!({}))
+        },
+        None => {
+            let reply = rule.reply.replace(BASE_URL_VAR, &st.base_url);
+            let prompt_tokens = (prompt.len() / 4) as u64;
+            let completion_tokens = (reply.len() / 4) as u64;
+            RawResp::json(
+                200,
+                json!({
+                    "id": "chatcmpl-mock",
+                    "object": "chat.completion",
+                    "model": body["model"].as_str().unwrap_or("mock"),
+                    "choices": [{
+                        "index": 0,
+                        "finish_reason": "stop",
+                        "message": {"role": "assistant", "content": reply}
+                    }],
+                    "usage": {
+                        "prompt_tokens": prompt_tokens,
+                        "completion_tokens": completion_tokens,
+                        "total_tokens": prompt_tokens + completion_tokens
+                    }
+                }),
+            )
+        }
+    };
+    Some(resp)
+}
@@ -0,0 +1,82 @@
+//! Fixtures shared by the unit tests: a context on the mock server and the `foo` component.
+
+use std::{fs, path::Path};
+
+use color_eyre::eyre::Result;
+use tempfile::TempDir;
+
+use crate::{
+    context::Context,
+    mock_llm::{MockPage, MockRule, MockScript, MockServer},
+};
+
+/// context whose llm api is `server`, llm cache and built-in registry off;
+/// `extra` is appended to the `[api]` table
+pub(crate) fn mock_ctx(
+    server: &MockServer,
+    base_dir: impl AsRef<Path>,
+    extra: &str,
+) -> Result<Context> {
+    let toml_str = format!(
+        r#"
+[api]
+key = "test-key"
+api_url = "{}"
+model_id = "mock-model"
+retry_delay = 0
+{extra}
+
+[download]
+username = "user"
+base_dir = {:?}
+components_file = "/nonexistent/components.toml"
+
+[cache]
+enabled = false
+
+[prompts]
+dir = "/nonexistent/prompts"
+"#,
+        server.chat_url(),
+        base_dir.as_ref().to_string_lossy()
+    );
+    let mut config = crate::config::load_config_from_str(&toml_str)?;
+    // the built-in registry hosts are real sites
+    config.registry.builtin = false;
+    Context::new(config)
+}
+
+/// component foo with two releases and a signature on `/foo/`
+pub(crate) fn foo_script() -> MockScript {
+    MockScript::default()
+        .rule(MockRule::reply(
+            "official download URL for source code of the open-source component foo",
+            r#"{"component_name": "foo", "available": true, "site_url": "{{base_url}}/foo/"}"#,
+        ))
+        .rule(MockRule::reply(
+            "(?s)extracted from a download page of the open-source component foo",
+            r#"```json
+{"verdicts": [
+    {"id": 0, "url": "{{base_url}}/foo/foo-1.0.tar.gz", "is_source_archive": true, "is_related": true, "reason": "release"},
+    {"id": 1, "url": "{{base_url}}/foo/foo-1.1.tar.gz", "is_source_archive": true, "is_related": true, "reason": "release"},
+]}
+```"#,
+        ))
+        .page(MockPage::html(
+            "/foo/",
+            r#"<a href="foo-1.0.tar.gz">foo-1.0.tar.gz</a>
+<a href="foo-1.1.tar.gz">foo-1.1.tar.gz</a>
+<a href="foo-1.1.tar.gz.sig">signature</a>
+<a href="/about.html">about</a>"#,
+        ))
+        .page(MockPage::archive("/foo/foo-1.0.tar.gz", "foo 1.0"))
+        .page(MockPage::archive("/foo/foo-1.1.tar.gz", "foo 1.1"))
+}
+
+/// fresh `<base_dir>/user/{GitHub/foo,Official}`, removed when dropped
+pub(crate) fn foo_base_dir() -> Result<TempDir> {
+    let base_dir = tempfile::tempdir()?;
+    fs::create_dir_all(base_dir.path().join("user/GitHub/foo"))?;
+    fs::create_dir_all(base_dir.path().join("user/Official"))?;
+    Ok(base_dir)
+}
@@ -1,14 +1,29 @@
-use std::time::Duration;
+use std::{
+    future::Future,
+    sync::{Once, OnceLock},
+    time::Duration,
+};
 
 use color_eyre::eyre::Result;
 use colored::*;
 use eyre::bail;
-use reqwest::{Client, Method, Response, Url};
+use rand::Rng;
+use reqwest::{
+    Client, Method, RequestBuilder, Response, StatusCode, Url,
+    header::{
+        CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, HeaderMap, HeaderName, LAST_MODIFIED,
+        LOCATION, RANGE, RETRY_AFTER,
+    },
+    redirect,
+};
 use tokio::sync::Semaphore;
 
-use crate::llm_api::{
-    config::{get_api_check_retry, get_api_retry_delay, get_api_timeout, get_parralel_count},
-    entities::ReqBody,
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cassette::Exchange,
+    context::Context,
+    llm_api::{config::ApiConfig, rate_limit::RateLimiter},
 };
 
 fn my_format(
@@ -40,41 +55,224 @@ pub fn init_flexi_logger() -> Result<()> {
     Ok(())
 }
 
-/// return accessibility along with resutl url
-pub async fn is_url_accessible(url: &str) -> (bool, Option<String>) {
-    let client = Client::new();
-    let retry = get_api_check_retry();
-    let delay = get_api_retry_delay();
-    let time_out = get_api_timeout();
+/// longest redirect chain followed by an accessibility check
+pub(crate) const MAX_REDIRECTS: usize = 10;
+
+/// outcome of an accessibility check of a url
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UrlAccess {
+    /// status of the last answer, none if the url never answered
+    #[serde(default)]
+    pub status: Option<u16>,
+    /// status of the HEAD request when it was retried as a GET
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub head_status: Option<u16>,
+    /// url after redirects
+    #[serde(default)]
+    pub final_url: Option<String>,
+    /// urls redirected from, the requested one first; empty without redirects
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redirects: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// size of the whole file, also for a ranged answer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_length: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    /// why no answer came back
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl UrlAccess {
+    fn failed(error: String) -> Self {
+        Self {
+            error: Some(error),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_accessible(&self) -> bool {
+        self.status
+            .is_some_and(|it| StatusCode::from_u16(it).is_ok_and(|it| it.is_success()))
+    }
+
+    /// short account of the check, e.g. `HEAD 405, GET 206`
+    pub fn describe(&self) -> String {
+        match (self.head_status, self.status) {
+            (Some(head), Some(get)) => format!("HEAD {head}, GET {get}"),
+            (None, Some(status)) => format!("HEAD {status}"),
+            _ => format!(
+                "no answer: {}",
+                self.error.as_deref().unwrap_or("unknown error")
+            ),
+        }
+    }
+}
+
+/// Check whether the url answers with a success status, following redirects.
+/// A refused HEAD is retried as a ranged GET, as many servers only implement GET.
+pub async fn is_url_accessible(ctx: &Context, url: &str) -> UrlAccess {
+    let cassette = ctx.cassette();
+    if cassette.is_replay() {
+        return match cassette.take("head", url) {
+            Ok(Exchange::Head { access, .. }) => access,
+            Ok(_) => unreachable!("exchanges are looked up by kind"),
+            Err(e) => {
+                log::warn!("Url Access Check: {}", e);
+                UrlAccess::failed(e.to_string())
+            }
+        };
+    }
+    let access = check_url_access(ctx.api(), url).await;
+    cassette.put(&Exchange::Head {
+        url: url.to_string(),
+        access: access.clone(),
+    });
+    access
+}
+
+async fn check_url_access(api: &ApiConfig, url: &str) -> UrlAccess {
+    let head = send_access_check(api, Method::HEAD, url).await;
+    let Some(head_status) = head.status.filter(|_| !head.is_accessible()) else {
+        return head;
+    };
+    log::debug!(
+        "Url Access Check: HEAD of {} answered {}, retry with GET",
+        url,
+        head_status
+    );
+    let get = send_access_check(api, Method::GET, url).await;
+    if get.status.is_none() {
+        return head;
+    }
+    UrlAccess {
+        head_status: Some(head_status),
+        ..get
+    }
+}
+
+/// client of accessibility checks, shared by all of them; redirects are followed by
+/// [`send_following`] so that each request records its own chain
+fn access_client() -> &'static Client {
+    static CLIENT: OnceLock<Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        Client::builder()
+            .redirect(redirect::Policy::none())
+            .build()
+            .expect("failed to build the access check client")
+    })
+}
 
-    for _ in 0..retry {
-        let resp = client
-            .request(Method::HEAD, url) // Use HEAD request for efficiency
-            .timeout(std::time::Duration::from_secs(time_out as u64)) // Optional: Set a timeout
-            .send()
-            .await;
+/// Send the request `build` makes for each url, following up to [`MAX_REDIRECTS`] redirects
+/// of a client without a redirect policy. `refuse` may turn down any url before it is
+/// requested. Returns the last answer and the urls redirected from, the requested one first.
+pub(crate) async fn send_following(
+    url: &str,
+    build: impl Fn(Url) -> RequestBuilder,
+    refuse: impl Fn(&Url) -> Option<String>,
+) -> Result<(Response, Vec<String>)> {
+    let mut cur = Url::parse(url)?;
+    let mut redirects: Vec<String> = vec![];
+    loop {
+        if let Some(reason) = refuse(&cur) {
+            bail!("refused to open {}: {}", cur, reason);
+        }
+        let resp = build(cur.clone()).send().await?;
+        let location = header_str(resp.headers(), LOCATION);
+        let Some(location) = location.filter(|_| resp.status().is_redirection()) else {
+            return Ok((resp, redirects));
+        };
+        if redirects.len() >= MAX_REDIRECTS {
+            bail!("too many redirects from {}", url);
+        }
+        let next = cur.join(&location)?;
+        redirects.push(cur.to_string());
+        cur = next;
+    }
+}
 
-        if let Ok(resp) = resp {
-            let url = resp.url().to_string();
-            return (resp.status().is_success(), Some(url));
+/// One request with retries on network errors, a GET asks for the first byte only
+/// and drops the body unread.
+async fn send_access_check(api: &ApiConfig, method: Method, url: &str) -> UrlAccess {
+    let retry = api.get_api_check_retry();
+    let policy = RetryPolicy::from_api(api);
+    let time_out = Duration::from_secs(api.get_api_timeout() as u64);
+    let build = |url: Url| {
+        let req = access_client()
+            .request(method.clone(), url)
+            .timeout(time_out);
+        if method == Method::GET {
+            req.header(RANGE, "bytes=0-0")
         } else {
-            log::warn!(
-                "Url Access Check: failed to access url {}, retry after {} seconds...",
-                url,
-                delay
-            );
-            tokio::time::sleep(Duration::from_secs(delay as u64)).await;
-            continue;
+            req
+        }
+    };
+
+    let mut error = String::new();
+    for att in 1..=retry {
+        match send_following(url, build, |_| None).await {
+            Ok((resp, redirects)) => return access_of(&resp, redirects),
+            Err(e) => {
+                error = e.to_string();
+                if att == retry {
+                    break;
+                }
+                let delay = policy.backoff(att as usize);
+                log::warn!(
+                    "Url Access Check: failed to access url {}, retry after {:.1} seconds...",
+                    url,
+                    delay.as_secs_f64()
+                );
+                tokio::time::sleep(delay).await;
+            }
         }
     }
+    log::warn!("Url Access Check: failed to access url {}: {}", url, error);
+    UrlAccess::failed(error)
+}
 
-    (false, None)
+fn header_str(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    Some(headers.get(name)?.to_str().ok()?.trim().to_string())
 }
 
+/// size of the whole file: the total of `Content-Range` for a ranged answer, else `Content-Length`
+fn whole_length(status: StatusCode, headers: &HeaderMap) -> Option<u64> {
+    if status == StatusCode::PARTIAL_CONTENT {
+        let range = header_str(headers, CONTENT_RANGE)?;
+        return range.rsplit_once('/')?.1.parse().ok();
+    }
+    header_str(headers, CONTENT_LENGTH)?.parse().ok()
+}
+
+fn access_of(resp: &Response, redirects: Vec<String>) -> UrlAccess {
+    let headers = resp.headers();
+    UrlAccess {
+        status: Some(resp.status().as_u16()),
+        head_status: None,
+        final_url: Some(resp.url().to_string()),
+        redirects,
+        content_type: header_str(headers, CONTENT_TYPE),
+        content_length: whole_length(resp.status(), headers),
+        last_modified: header_str(headers, LAST_MODIFIED),
+        error: None,
+    }
+}
+
+/// init logger and error report hooks, only the first call takes effect
 pub fn init_report_utils() -> Result<()> {
-    init_flexi_logger()?;
-    color_eyre::install()?;
-    Ok(())
+    static INIT: Once = Once::new();
+    let mut res = Ok(());
+    INIT.call_once(|| {
+        res = init_flexi_logger().map(|_| {
+            // an eyre report created earlier installs the default hook
+            if let Err(e) = color_eyre::install() {
+                log::debug!("color-eyre hook not installed: {}", e);
+            }
+        });
+    });
+    res
 }
 
 pub fn is_absolute_url(url: &str) -> bool {
@@ -82,72 +280,204 @@ pub fn is_absolute_url(url: &str) -> bool {
     url_par.is_ok()
 }
 
-pub fn construct_semaphore() -> Semaphore {
-    let max_concur = get_parralel_count();
+pub fn construct_semaphore(api: &ApiConfig) -> Semaphore {
+    let max_concur = api.get_parralel_count();
     Semaphore::new(max_concur)
 }
 
-pub async fn get_with_retry(
-    cli: &Client,
-    url: &str,
-    retry: usize,
-    retry_delay: usize,
-) -> Result<Response> {
-    let mut att = 0;
+/// retry count and backoff bounds of a request
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub retry: usize,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
 
-    while att < retry {
-        att += 1;
+impl RetryPolicy {
+    pub fn from_api(api: &ApiConfig) -> Self {
+        Self {
+            retry: api.get_api_retry(),
+            base_delay: Duration::from_secs(api.get_api_retry_delay() as u64),
+            max_delay: Duration::from_secs(api.get_api_max_retry_delay() as u64),
+        }
+    }
 
-        let resp_res = cli.get(url).send().await;
-        match resp_res {
-            Err(e) => {
-                log::warn!("Failed to request url {}: {}.", url, e);
-                log::warn!("Retry after {} seconds...", retry_delay);
-                tokio::time::sleep(Duration::from_secs(retry_delay as u64)).await;
-                continue;
-            }
-            Ok(resp) => {
-                return Ok(resp);
-            }
+    /// exponential backoff after attempt `att` (1 based), jittered within its upper half
+    pub fn backoff(&self, att: usize) -> Duration {
+        let exp = 2u32.saturating_pow(att.saturating_sub(1) as u32);
+        let delay = self.base_delay.saturating_mul(exp).min(self.max_delay);
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+
+    /// `Retry-After` of the server if given, at most `max_delay`, else the backoff
+    pub fn delay(&self, att: usize, retry_after: Option<Duration>) -> Duration {
+        match retry_after {
+            Some(delay) => delay.min(self.max_delay),
+            None => self.backoff(att),
         }
     }
+}
 
-    bail!(
-        "Failed to get to get reponse from url {} with max retry of {}",
-        url,
-        retry
-    );
+/// throttling and server side failures, worth another attempt
+fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::REQUEST_TIMEOUT
+            | StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
 }
 
-pub async fn post_with_retry(
+/// `Retry-After` as delay seconds or an http date
+fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let val = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = val.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(val).ok()?;
+    let delta = date.with_timezone(&chrono::Utc) - chrono::Utc::now();
+    Some(delta.to_std().unwrap_or(Duration::ZERO))
+}
+
+/// Send until the response is not retryable or attempts run out.
+/// The last response is returned even if its status is still retryable.
+async fn send_with_retry<F, Fut>(url: &str, policy: &RetryPolicy, mut send: F) -> Result<Response>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = reqwest::Result<Response>>,
+{
+    let mut att = 0;
+    loop {
+        att += 1;
+        let (reason, retry_after, last) = match send().await {
+            Ok(resp) if !is_retryable_status(resp.status()) => return Ok(resp),
+            Ok(resp) => (
+                format!("status {}", resp.status()),
+                parse_retry_after(resp.headers()),
+                Some(resp),
+            ),
+            Err(e) => (e.to_string(), None, None),
+        };
+        if att >= policy.retry {
+            return match last {
+                Some(resp) => Ok(resp),
+                None => bail!(
+                    "Failed to get reponse from url {} with max retry of {}: {}",
+                    url,
+                    policy.retry,
+                    reason
+                ),
+            };
+        }
+        let delay = policy.delay(att, retry_after);
+        log::warn!("Failed to request url {}: {}.", url, reason);
+        log::warn!("Retry after {:.1} seconds...", delay.as_secs_f64());
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// GET with retries, an error status that is not retryable is returned to the caller
+pub async fn get_with_retry(cli: &Client, url: &str, policy: &RetryPolicy) -> Result<Response> {
+    send_with_retry(url, policy, || cli.get(url).send()).await
+}
+
+/// POST json to the llm api. The rate limiter is acquired once for the request, as it
+/// is settled once with the usage. Fails with the response body on any non-success status.
+pub async fn post_with_retry<T: Serialize + ?Sized>(
     cli: &Client,
     url: &str,
-    payload: &ReqBody,
-    retry: usize,
-    retry_delay: usize,
+    payload: &T,
+    policy: &RetryPolicy,
+    limiter: &RateLimiter,
+    est_tokens: u64,
 ) -> Result<Response> {
-    let mut att = 0;
+    limiter.acquire(est_tokens).await;
+    let resp = send_with_retry(url, policy, || cli.post(url).json(payload).send()).await?;
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.text().await.unwrap_or_default();
+        bail!("Request to {} failed with status {}: {}", url, status, body);
+    }
+    Ok(resp)
+}
 
-    while att < retry {
-        att += 1;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mock_llm::{MockPage, MockScript, MockServer},
+        test_util::mock_ctx,
+    };
+    use reqwest::header::HeaderValue;
 
-        let resp_res = cli.post(url).json(payload).send().await;
-        match resp_res {
-            Err(e) => {
-                log::warn!("Failed to request url {}: {}.", url, e);
-                log::warn!("Retry after {} seconds...", retry_delay);
-                tokio::time::sleep(Duration::from_secs(retry_delay as u64)).await;
-                continue;
-            }
-            Ok(resp) => {
-                return Ok(resp);
-            }
-        }
+    #[test]
+    fn test_backoff_and_retry_after() {
+        let policy = RetryPolicy {
+            retry: 5,
+            base_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(5),
+        };
+        let delay = policy.backoff(2);
+        assert!(delay >= Duration::from_secs(2) && delay <= Duration::from_secs(4));
+        assert!(policy.backoff(10) <= Duration::from_secs(5));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("7"));
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(7)));
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(parse_retry_after(&headers), Some(Duration::ZERO));
+        // a far away `Retry-After` is cut to the upper bound
+        assert_eq!(
+            policy.delay(1, Some(Duration::from_secs(86400))),
+            Duration::from_secs(5)
+        );
+        assert!(is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!is_retryable_status(StatusCode::BAD_REQUEST));
+
+        headers.insert(CONTENT_RANGE, HeaderValue::from_static("bytes 0-0/1234"));
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("1"));
+        assert_eq!(
+            whole_length(StatusCode::PARTIAL_CONTENT, &headers),
+            Some(1234)
+        );
+        assert_eq!(whole_length(StatusCode::OK, &headers), Some(1));
     }
 
-    bail!(
-        "Failed to get to get reponse from url {} with max retry of {}",
-        url,
-        retry
-    );
+    #[tokio::test]
+    async fn test_url_access_falls_back_to_get() -> Result<()> {
+        let script = MockScript::default()
+            .page(MockPage::redirect("/old/", "{{base_url}}/new/"))
+            .page(MockPage::redirect("/new/", "/pub/foo-1.0.tar.gz"))
+            .page(MockPage::archive("/pub/foo-1.0.tar.gz", "tarball").no_head())
+            .page(MockPage::redirect("/loop/", "/loop/"));
+        let server = MockServer::start(script).await?;
+        let ctx = mock_ctx(&server, "/nonexistent", "")?;
+        let url = |path: &str| format!("{}{}", server.base_url(), path);
+
+        let access = is_url_accessible(&ctx, &url("/old/")).await;
+        assert!(access.is_accessible(), "{access:?}");
+        assert_eq!(access.describe(), "HEAD 405, GET 200");
+        assert_eq!(access.final_url, Some(url("/pub/foo-1.0.tar.gz")));
+        assert_eq!(access.redirects, [url("/old/"), url("/new/")]);
+        assert_eq!(access.content_type.as_deref(), Some("application/gzip"));
+        assert_eq!(access.content_length, Some(7));
+
+        let access = is_url_accessible(&ctx, &url("/missing")).await;
+        assert!(!access.is_accessible());
+        assert_eq!(access.describe(), "HEAD 404, GET 404");
+
+        let access = is_url_accessible(&ctx, &url("/loop/")).await;
+        assert!(
+            access
+                .error
+                .is_some_and(|it| it.contains("too many redirects"))
+        );
+        Ok(())
+    }
 }
@@ -1,3 +1,3 @@
 #!/bin/bash
 
-RUST_LOG=info cargo run -r --bin download -- --base-dir /home/yqy/toys/osdl/official_download/output
+RUST_LOG=info cargo run -r --bin official-download -- --base-dir /home/yqy/toys/osdl/official_download/output run